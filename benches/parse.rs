@@ -0,0 +1,45 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// Parsing a large generated zones file, the step the parallel feature spreads over all cores.
+// Compare `cargo bench` with `cargo bench --features parallel`; the sequential case is the same
+// either way, as a baseline.
+
+use std::path::PathBuf;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use bind_manager::zones::{domains_in, parse_domain_from_line, ZoneSource};
+
+const ZONES: usize = 500_000;
+
+fn zones_file() -> String {
+    let mut contents = String::from("# Generated for the benchmark\n\n");
+    for idx in 0..ZONES {
+        contents.push_str(&format!("zone \"host{}.example{}.com\" {{type master; file \"/etc/bind/zones/master/blockeddomains.db\";}};\n\n", idx, idx % 97));
+    }
+    contents
+}
+
+fn parse(c: &mut Criterion) {
+    let contents = zones_file();
+    let mut group = c.benchmark_group("parse 500k zones");
+    group.throughput(Throughput::Bytes(contents.len() as u64));
+    group.sample_size(20);
+
+    group.bench_function("sequential", |b| b.iter(|| contents.lines().filter_map(parse_domain_from_line).collect::<Vec<String>>()));
+    let sources = || vec![ZoneSource { path: PathBuf::from("/etc/bind/blacklisted.zones"), contents: contents.clone() }];
+    group.bench_function(if cfg!(feature = "parallel") { "domains_in (parallel)" } else { "domains_in" }, |b| {
+        b.iter_batched(sources, |sources| domains_in(&sources), BatchSize::LargeInput)
+    });
+    group.finish();
+
+    // Whichever path domains_in takes, it has to find exactly the same zones
+    let parsed = domains_in(&sources());
+    assert_eq!(parsed, contents.lines().filter_map(parse_domain_from_line).collect::<Vec<String>>());
+    assert_eq!(parsed.len(), ZONES);
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
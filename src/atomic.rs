@@ -0,0 +1,256 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File, OpenOptions};
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// Replaces the contents of `path` so readers see either the old or the new file, never a partial one.
+//
+// The temp file is created next to the target so the rename stays on the same filesystem. If that
+// isn't possible (a read-only filesystem with the target bind-mounted writable on top, so creating
+// fails with EROFS or the rename with EXDEV or EBUSY) we fall back to copying the contents over the
+// target and syncing it. Any other error is returned with the target untouched.
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    write_verified(path, contents, &|_| Ok(()))
+}
+
+// Like `write`, but `verify` is run on the temp file as read back from disk before it replaces the
+// target, so a bad serialization or a write cut short by a full disk never swaps out a good file.
+// When the file has to be written in place, only the contents about to be written can be checked.
+//
+// A symlink is followed, so the file it points at is replaced and the link stays in place.
+pub fn write_verified(path: &Path, contents: &[u8], verify: &dyn Fn(&[u8]) -> Result<(), String>) -> io::Result<()> {
+    write_with_rename(path, contents, verify, &|from, to| fs::rename(from, to))
+}
+
+// The rename is passed in so the fallback for a failing one can be exercised
+fn write_with_rename(path: &Path, contents: &[u8], verify: &dyn Fn(&[u8]) -> Result<(), String>, rename: &dyn Fn(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
+    let target;
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            target = fs::canonicalize(path)?;
+            target.as_path()
+        }
+        _ => path,
+    };
+    let new_file = !path.exists();
+    let (temp_file, temp_path) = match create_temp_file(path) {
+        Ok(created) => created,
+        Err(err) if must_write_in_place(&err) => {
+            verify(contents).map_err(verification_failed)?;
+            return write_in_place(path, contents);
+        }
+        Err(err) => return Err(err),
+    };
+
+    let filled = fill_temp_file(temp_file, contents, new_file)
+        .and_then(|()| fs::read(&temp_path))
+        .and_then(|written| verify(&written).map_err(verification_failed));
+    if let Err(err) = filled {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+
+    match rename(&temp_path, path) {
+        Ok(()) => {
+            sync_parent_dir(path);
+            Ok(())
+        }
+        Err(err) if must_write_in_place(&err) => {
+            let _ = fs::remove_file(&temp_path);
+            write_in_place(path, contents)
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+// The errors that mean the file can't be replaced by a rename, only overwritten where it is
+fn must_write_in_place(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::ReadOnlyFilesystem | io::ErrorKind::CrossesDevices | io::ErrorKind::ResourceBusy)
+}
+
+// Randomly keyed by the OS, so a temp file left behind by a crash is never picked up again
+fn temp_path_for(path: &Path, attempt: u32) -> PathBuf {
+    let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp_name = format!(".{}.tmp-{:016x}", file_name, RandomState::new().hash_one(attempt));
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.join(temp_name),
+        _ => PathBuf::from(temp_name),
+    }
+}
+
+// Creates the temp file private to us, then gives it the mode and owner of the file it replaces
+// (so BIND can still read it) before anything is written to it
+fn create_temp_file(path: &Path) -> io::Result<(File, PathBuf)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut attempt = 0;
+    let (file, temp_path) = loop {
+        let temp_path = temp_path_for(path, attempt);
+        match options.open(&temp_path) {
+            Ok(file) => break (file, temp_path),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(err) => return Err(err),
+        }
+    };
+
+    if let Ok(metadata) = fs::metadata(path) {
+        let owned = file.set_permissions(metadata.permissions());
+        #[cfg(unix)]
+        let owned = owned.and_then(|()| {
+            use std::os::unix::fs::MetadataExt;
+            // Only root can give a file away, anyone else keeps owning what they write
+            match std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid())) {
+                Err(err) if err.kind() == io::ErrorKind::PermissionDenied => Ok(()),
+                chowned => chowned,
+            }
+        });
+        if let Err(err) = owned {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+    }
+    Ok((file, temp_path))
+}
+
+fn fill_temp_file(mut file: File, contents: &[u8], new_file: bool) -> io::Result<()> {
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    // A new file only becomes readable once it is complete, then by everyone like the rest of
+    // /etc/bind, so named can load it
+    #[cfg(unix)]
+    if new_file {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o644))?;
+    }
+    #[cfg(not(unix))]
+    let _ = new_file;
+    Ok(())
+}
+
+fn verification_failed(problem: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("the new contents did not read back correctly ({}), the original file was kept", problem))
+}
+
+fn write_in_place(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).truncate(true).create(true).open(path)?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+fn sync_parent_dir(path: &Path) {
+    // Persist the rename itself; not every platform lets us open a directory, so this is best effort
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A fresh directory of its own under the system temp dir
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bind_manager-atomic-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cross_device_rename_falls_back_to_the_same_file_and_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir("exdev");
+        let (renamed, copied) = (dir.join("renamed.zones"), dir.join("copied.zones"));
+        for path in [&renamed, &copied] {
+            fs::write(path, "old contents, longer than the new ones\n").unwrap();
+            fs::set_permissions(path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+
+        let contents = b"zone \"example.com\" {type master; file \"/db\";};\n";
+        write(&renamed, contents).unwrap();
+        let cross_device = |_: &Path, _: &Path| Err(io::Error::new(io::ErrorKind::CrossesDevices, "cross-device link"));
+        write_with_rename(&copied, contents, &|_| Ok(()), &cross_device).unwrap();
+
+        assert_eq!(fs::read(&copied).unwrap(), contents);
+        assert_eq!(fs::read(&copied).unwrap(), fs::read(&renamed).unwrap());
+        assert_eq!(mode(&copied), 0o640);
+        assert_eq!(mode(&copied), mode(&renamed));
+        // The temp file the rename would have moved is cleaned up
+        let mut left: Vec<String> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        left.sort();
+        assert_eq!(left, vec!["copied.zones", "renamed.zones"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn other_rename_errors_keep_the_old_file() {
+        let dir = scratch_dir("denied");
+        let path = dir.join("blacklisted.zones");
+        fs::write(&path, "old\n").unwrap();
+
+        let denied = |_: &Path, _: &Path| Err(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+        assert!(write_with_rename(&path, b"new\n", &|_| Ok(()), &denied).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old\n");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_temp_file_is_locked_down_before_anything_is_written() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir("temp-mode");
+        let existing = dir.join("blacklisted.zones");
+        fs::write(&existing, "old\n").unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o640)).unwrap();
+
+        // Empty, with the mode of the file it replaces or private for a new one
+        let (_, replacing) = create_temp_file(&existing).unwrap();
+        let (_, creating) = create_temp_file(&dir.join("new.zones")).unwrap();
+        assert_eq!((fs::metadata(&replacing).unwrap().len(), mode(&replacing)), (0, 0o640));
+        assert_eq!((fs::metadata(&creating).unwrap().len(), mode(&creating)), (0, 0o600));
+        // Left behind, as by a crash, and not reused
+        let (_, again) = create_temp_file(&existing).unwrap();
+        assert_ne!(again, replacing);
+
+        // Once complete a new file is readable for named
+        write(&dir.join("new.zones"), b"new\n").unwrap();
+        assert_eq!(mode(&dir.join("new.zones")), 0o644);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_an_unrenameable_target_is_written_in_place() {
+        for kind in [io::ErrorKind::ReadOnlyFilesystem, io::ErrorKind::CrossesDevices, io::ErrorKind::ResourceBusy] {
+            assert!(must_write_in_place(&io::Error::new(kind, "")), "{:?}", kind);
+        }
+        for kind in [io::ErrorKind::PermissionDenied, io::ErrorKind::StorageFull, io::ErrorKind::AlreadyExists, io::ErrorKind::NotFound] {
+            assert!(!must_write_in_place(&io::Error::new(kind, "")), "{:?}", kind);
+        }
+    }
+}
@@ -0,0 +1,160 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// Where the zones file, its includes, the reason log and the hosts file are read from and written
+// to. The real filesystem normally, or memory so the operations can be exercised without /etc/bind.
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::{atomic, debug};
+
+pub trait FileBackend: Debug + Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    // Replaces the whole file, so readers never see a partial write
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    // Adds to the end of an existing file without rewriting it. Unlike write it isn't atomic, a
+    // reader can see part of the addition.
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    // Deletes the file, succeeding if it is already gone
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    // Writes only if `verify` accepts the contents, keeping the old file otherwise. Backends that
+    // stage writes check the staged copy as it reads back instead.
+    fn write_verified(&self, path: &Path, contents: &[u8], verify: &dyn Fn(&[u8]) -> Result<(), String>) -> io::Result<()> {
+        verify(contents).map_err(|problem| io::Error::new(io::ErrorKind::InvalidData, problem))?;
+        self.write(path, contents)
+    }
+
+    // The path include loops are detected with, failing if the file doesn't exist
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.canonicalize(path).is_ok()
+    }
+
+    // When the file last changed, for noticing it changed since; unsupported by default
+    fn modified(&self, _path: &Path) -> io::Result<SystemTime> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "modification times are not tracked"))
+    }
+}
+
+// The real files, written atomically
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Filesystem;
+
+impl FileBackend for Filesystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        debug::timed(format_args!("reading {}", path.display()), || fs::read(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        debug::timed(format_args!("writing {} ({} bytes)", path.display(), contents.len()), || atomic::write(path, contents))
+    }
+
+    fn write_verified(&self, path: &Path, contents: &[u8], verify: &dyn Fn(&[u8]) -> Result<(), String>) -> io::Result<()> {
+        debug::timed(format_args!("writing {} ({} bytes)", path.display(), contents.len()), || atomic::write_verified(path, contents, verify))
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        debug::timed(format_args!("appending to {} ({} bytes)", path.display(), contents.len()), || {
+            let mut file = fs::OpenOptions::new().append(true).open(path)?;
+            file.write_all(contents)?;
+            file.sync_data()
+        })
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    // A dangling symlink exists too, so reading it fails with an error saying so instead of it
+    // passing for a missing file
+    fn exists(&self, path: &Path) -> bool {
+        fs::symlink_metadata(path).is_ok()
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+}
+
+pub fn filesystem() -> Arc<dyn FileBackend> {
+    Arc::new(Filesystem)
+}
+
+// Files kept in memory by path, starting out with whatever they were given
+#[derive(Debug, Default)]
+pub struct InMemory {
+    files: Mutex<HashMap<PathBuf, Vec<u8>>>,
+}
+
+impl InMemory {
+    pub fn new() -> InMemory {
+        InMemory::default()
+    }
+
+    pub fn with_file(self, path: impl Into<PathBuf>, contents: impl Into<Vec<u8>>) -> InMemory {
+        self.files.lock().unwrap_or_else(|err| err.into_inner()).insert(path.into(), contents.into());
+        self
+    }
+
+    // The current contents of a file, None if it was never written
+    pub fn contents(&self, path: &Path) -> Option<String> {
+        let files = self.files.lock().unwrap_or_else(|err| err.into_inner());
+        files.get(path).map(|contents| String::from_utf8_lossy(contents).into_owned())
+    }
+}
+
+impl FileBackend for InMemory {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let files = self.files.lock().unwrap_or_else(|err| err.into_inner());
+        files.get(path).cloned().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file in memory"))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.files.lock().unwrap_or_else(|err| err.into_inner()).insert(path.to_path_buf(), contents.to_vec());
+        Ok(())
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap_or_else(|err| err.into_inner());
+        let file = files.get_mut(path).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file in memory"))?;
+        file.extend_from_slice(contents);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.files.lock().unwrap_or_else(|err| err.into_inner()).remove(path);
+        Ok(())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let files = self.files.lock().unwrap_or_else(|err| err.into_inner());
+        match files.contains_key(path) {
+            true => Ok(path.to_path_buf()),
+            false => Err(io::Error::new(io::ErrorKind::NotFound, "no such file in memory")),
+        }
+    }
+}
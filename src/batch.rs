@@ -0,0 +1,408 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use chrono::Utc;
+
+use bind_manager::config::{CommentedZones, Config};
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::zones::{self, parse_domain_from_line};
+use bind_manager::{
+    append_zones_file, check_zone_db, ensure_catch_all, ensure_header, entry_zone_file, load_reason_log, merge_entry, normalize_domain, read_zone_domains, read_zones_file,
+    render_zone, save_reason_log, validate_domain, write_zones_file, DomainEntry, KnownGood,
+};
+
+use crate::{check_blockable_with, reload_bind, report_catch_all, report_commented, summary};
+
+// The reason log and zones file held in memory, so many operations cost one load and one flush
+// instead of a full read and rewrite each.
+pub struct Workspace<'a> {
+    config: &'a Config,
+    entries: Vec<DomainEntry>,
+    zone_contents: String,
+    zone_set: HashSet<String>,
+    // The subset of zone_set defined in the zones file itself rather than an included file, which is all we rewrite
+    owned: HashSet<String>,
+    appended: Vec<String>,
+    removed: HashSet<String>,
+    entries_dirty: bool,
+    // Rewrite the zones file in domain order on commit
+    sorted: bool,
+    // Append new zones to the zones file on flush rather than rewriting it
+    append: bool,
+    // Whether zone_contents already has the header and catch-all, so new zones can just be appended
+    complete: bool,
+    // Zones commented out in the zones file, which adds leave alone with commented_zones present
+    commented: HashSet<String>,
+    // The zone db files already checked for, so a missing one is warned about once rather than per zone
+    checked_zone_dbs: HashSet<String>,
+}
+
+impl<'a> Workspace<'a> {
+    pub fn load(config: &'a Config) -> Result<Workspace<'a>> {
+        let contents = read_zones_file(config)?;
+        let zone_set: HashSet<String> = read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).collect();
+        let owned: HashSet<String> = contents
+            .lines()
+            .filter_map(parse_domain_from_line)
+            .map(|domain| normalize_domain(&domain))
+            .filter(|domain| zone_set.contains(domain))
+            .collect();
+        let commented = match config.commented_zones {
+            CommentedZones::Present => zones::commented_domains(&contents).iter().map(|domain| normalize_domain(domain)).collect(),
+            CommentedZones::Absent => HashSet::new(),
+        };
+        let mut normalized = contents.as_bytes().to_vec();
+        let complete = !ensure_catch_all(config, &mut normalized) && !ensure_header(config, &mut normalized);
+
+        Ok(Workspace {
+            config,
+            entries: load_reason_log(config)?,
+            zone_contents: contents,
+            zone_set,
+            owned,
+            appended: Vec::new(),
+            removed: HashSet::new(),
+            entries_dirty: false,
+            sorted: false,
+            append: false,
+            complete,
+            commented,
+            checked_zone_dbs: HashSet::new(),
+        })
+    }
+
+    // Returns whether anything changed
+    pub fn add(&mut self, new_entry: DomainEntry) -> Result<bool> {
+        let domain = normalize_domain(&new_entry.domain);
+        validate_domain(&domain)?;
+
+        if !self.zone_set.contains(&domain) && self.commented.contains(&domain) {
+            report_commented(self.config, &domain);
+            logging::event(Level::Info, "add", Some(&domain), "commented out", None);
+            return Ok(false);
+        }
+
+        let mut changed = false;
+        let mut added = false;
+        if !self.zone_set.contains(&domain) {
+            // BIND refuses to load the zones if the db file a zone points at is missing
+            let zone_file = entry_zone_file(self.config, &new_entry)?;
+            let zone_db = zone_file.unwrap_or(&self.config.zone_db);
+            if self.checked_zone_dbs.insert(zone_db.to_string()) {
+                if let Some(warning) = check_zone_db(self.config, zone_db) {
+                    println!("Warning! {}", warning);
+                }
+            }
+            self.appended.push(render_zone(self.config, &domain, zone_file)?);
+            self.zone_set.insert(domain.clone());
+            self.owned.insert(domain.clone());
+            self.removed.remove(&domain);
+            changed = true;
+            added = true;
+        }
+
+        match self.entries.iter_mut().find(|entry| normalize_domain(&entry.domain) == domain) {
+            Some(entry) => changed |= merge_entry(entry, new_entry),
+            None => {
+                self.entries.push(DomainEntry { domain, added_at: Some(Utc::now()), ..new_entry });
+                changed = true;
+            }
+        }
+        self.entries_dirty |= changed;
+        match (added, changed) {
+            (true, _) => summary::added(1),
+            (false, true) => summary::updated(1),
+            (false, false) => {}
+        }
+        Ok(changed)
+    }
+
+    // Returns whether the domain was present
+    pub fn remove(&mut self, domain: &str) -> bool {
+        let domain = normalize_domain(domain);
+        let entry_count = self.entries.len();
+        self.entries.retain(|entry| normalize_domain(&entry.domain) != domain);
+        let had_entry = self.entries.len() < entry_count;
+        self.entries_dirty |= had_entry;
+
+        let had_zone = self.owned.remove(&domain);
+        if had_zone {
+            self.zone_set.remove(&domain);
+            self.removed.insert(domain);
+        }
+        if had_entry || had_zone {
+            summary::removed(1);
+        }
+        had_entry || had_zone
+    }
+
+    // The normalized domains that currently have a zone, including zones in included files
+    pub fn domains(&self) -> &HashSet<String> {
+        &self.zone_set
+    }
+
+    pub fn entry(&self, domain: &str) -> Option<&DomainEntry> {
+        let domain = normalize_domain(domain);
+        self.entries.iter().find(|entry| normalize_domain(&entry.domain) == domain)
+    }
+
+    // The normalized domains whose zone is in the zones file itself, and so can be removed
+    pub fn owned_domains(&self) -> &HashSet<String> {
+        &self.owned
+    }
+
+    pub fn sort_zones(&mut self) {
+        self.sorted = true;
+    }
+
+    // For flushing every few thousand domains, where rewriting the whole file each time adds up.
+    // An append isn't atomic like a rewrite, a crash can leave a partial zone line behind.
+    pub fn append_new_zones(&mut self) {
+        self.append = true;
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.entries_dirty || !self.appended.is_empty() || !self.removed.is_empty()
+    }
+
+    // Writes whatever changed back to disk and reloads BIND once, only if the files actually changed
+    pub fn commit(mut self) -> Result<()> {
+        if self.flush()? {
+            reload_bind(self.config)?;
+        } else {
+            println!("Already up to date, nothing was written.");
+        }
+        Ok(())
+    }
+
+    // Writes whatever changed back to disk without reloading, returning whether anything was written.
+    // Files that would come out byte-identical aren't rewritten, and new zones alone are appended.
+    // The workspace stays usable, so a long run can flush as it goes.
+    pub fn flush(&mut self) -> Result<bool> {
+        if !self.is_dirty() && !self.sorted {
+            return Ok(false);
+        }
+
+        let mut zones_written = false;
+        if self.append && self.complete && !self.appended.is_empty() && self.removed.is_empty() && !self.sorted {
+            let mut contents = String::new();
+            if !self.zone_contents.is_empty() && !self.zone_contents.ends_with('\n') {
+                contents.push('\n');
+            }
+            for line in &self.appended {
+                contents.push_str(line);
+                contents.push_str("\n\n");
+            }
+            append_zones_file(self.config, contents.as_bytes())?;
+            self.zone_contents.push_str(&contents);
+            self.appended.clear();
+            zones_written = true;
+        } else if !self.appended.is_empty() || !self.removed.is_empty() || self.sorted {
+            let mut contents = String::new();
+            for line in self.zone_contents.lines() {
+                let removed = parse_domain_from_line(line).is_some_and(|domain| self.removed.contains(&normalize_domain(&domain)));
+                if !removed {
+                    contents.push_str(line);
+                    contents.push('\n');
+                }
+            }
+            for line in &self.appended {
+                let domain = parse_domain_from_line(line).map(|domain| normalize_domain(&domain));
+                if domain.is_some_and(|domain| !self.removed.contains(&domain)) {
+                    contents.push_str(line);
+                    contents.push_str("\n\n");
+                }
+            }
+
+            if self.sorted {
+                contents = sort_zones(&contents);
+            }
+
+            let mut contents = contents.into_bytes();
+            if ensure_catch_all(self.config, &mut contents) {
+                report_catch_all(self.config);
+            }
+            ensure_header(self.config, &mut contents);
+            zones_written = write_zones_file(self.config, &contents)?;
+            self.zone_contents = String::from_utf8_lossy(&contents).into_owned();
+            self.appended.clear();
+            self.removed.clear();
+            self.complete = true;
+        }
+
+        let mut entries_written = false;
+        if self.entries_dirty {
+            entries_written = save_reason_log(self.config, &self.entries)?;
+            self.entries_dirty = false;
+        }
+        Ok(zones_written || entries_written)
+    }
+}
+
+// Puts the zone statements in domain order, one blank line apart. Everything else (comments,
+// includes, the allowlist catch-all) is kept in its order at the top.
+fn sort_zones(contents: &str) -> String {
+    let mut preamble = Vec::new();
+    let mut zones = Vec::new();
+    for line in contents.lines() {
+        match parse_domain_from_line(line).map(|domain| normalize_domain(&domain)) {
+            Some(domain) if domain != "." => zones.push((domain, line)),
+            _ if line.trim().is_empty() => {}
+            _ => preamble.push(line),
+        }
+    }
+    zones.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut sorted = String::with_capacity(contents.len());
+    for line in &preamble {
+        sorted.push_str(line);
+        sorted.push('\n');
+    }
+    if !preamble.is_empty() {
+        sorted.push('\n');
+    }
+    for (_, line) in zones {
+        sorted.push_str(line);
+        sorted.push_str("\n\n");
+    }
+    sorted
+}
+
+// Runs add/del commands from a file (or stdin) against one workspace, committing once at the end.
+// Every line is parsed and checked before anything is applied, so a typo or a protected domain
+// doesn't leave a half-applied batch.
+pub fn run(config: &Config, file: Option<&str>, force: bool) -> Result<()> {
+    let input = match file {
+        Some(file) => fs::read_to_string(file).map_err(Error::reading(Path::new(file)))?,
+        None => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+
+    let known_good = KnownGood::load(config)?;
+    let mut operations = Vec::new();
+    for (idx, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.splitn(3, char::is_whitespace);
+        let operation = match (words.next(), words.next(), words.next()) {
+            (Some("add"), Some(domain), reason) => {
+                let reason = reason.map(str::trim).filter(|reason| !reason.is_empty());
+                let known = known_good.as_ref().and_then(|known_good| known_good.blocked_by(domain));
+                check_blockable_with(config, domain, known, force)
+                    .map_err(|err| Error::InvalidArgument(format!("line {}: {} Nothing was applied.", idx + 1, err)))?;
+                Operation::Add(domain.to_string(), reason.map(str::to_string))
+            }
+            (Some("del"), Some(domain), None) => Operation::Del(domain.to_string()),
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "line {}: expected \"add <domain> [reason]\" or \"del <domain>\", got \"{}\".",
+                    idx + 1,
+                    line
+                )))
+            }
+        };
+        operations.push(operation);
+    }
+
+    let mut workspace = Workspace::load(config)?;
+    let (mut changed, mut unchanged, mut not_found) = (0, 0, 0);
+    for operation in operations {
+        match operation {
+            Operation::Add(domain, reason) => {
+                let reason = reason.unwrap_or_else(|| config.default_reason.clone());
+                if workspace.add(DomainEntry { reason, ..DomainEntry::new(config, &domain) })? {
+                    changed += 1;
+                    logging::event(Level::Info, "batch", Some(&normalize_domain(&domain)), "added", None);
+                } else {
+                    unchanged += 1;
+                }
+            }
+            Operation::Del(domain) => {
+                if workspace.remove(&domain) {
+                    changed += 1;
+                    logging::event(Level::Info, "batch", Some(&normalize_domain(&domain)), "removed", None);
+                } else {
+                    not_found += 1;
+                    println!("Domain {} not found.", normalize_domain(&domain));
+                }
+            }
+        }
+    }
+
+    println!("Batch applied: {} changed, {} unchanged, {} not found.", changed, unchanged, not_found);
+    workspace.commit()
+}
+
+enum Operation {
+    Add(String, Option<String>),
+    Del(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use bind_manager::backend::InMemory;
+
+    // A list kept in memory, and the batch file to run against it
+    fn setup(name: &str, zones: &str, batch: &str) -> (Config, String) {
+        let defaults = Config::default();
+        let backend = Arc::new(InMemory::new().with_file(&defaults.zones_file, zones).with_file(&defaults.zone_db, ""));
+        let config = Config { no_reload: true, commented_zones: CommentedZones::Present, backend, ..defaults };
+        let path = std::env::temp_dir().join(format!("bind_manager-batch-{}-{}", name, std::process::id()));
+        fs::write(&path, batch).unwrap();
+        (config, path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn commented_zones_are_left_alone() {
+        let (config, path) = setup("commented", "# zone \"old.example\" {type master; file \"/db\";};\n", "add old.example phishing\nadd new.example malware\n");
+        run(&config, Some(&path), false).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_zone_domains(&config).unwrap(), vec!["new.example"]);
+        assert!(read_zones_file(&config).unwrap().starts_with("# zone \"old.example\""));
+        let entries = load_reason_log(&config).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.domain.as_str()).collect::<Vec<_>>(), vec!["new.example"]);
+    }
+
+    #[test]
+    fn a_batch_is_applied_in_one_go() {
+        let zones = "zone \"old.example\" {type master; file \"/db\";};\n\n";
+        let (config, path) = setup("apply", zones, "# cleanup\nadd a.example phishing\nadd b.example\ndel old.example\ndel missing.example\n");
+        run(&config, Some(&path), false).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_zone_domains(&config).unwrap(), vec!["a.example", "b.example"]);
+        let entries = load_reason_log(&config).unwrap();
+        let reasons: Vec<(&str, &str)> = entries.iter().map(|entry| (entry.domain.as_str(), entry.reason.as_str())).collect();
+        assert_eq!(reasons, vec![("a.example", "phishing"), ("b.example", config.default_reason.as_str())]);
+    }
+
+    #[test]
+    fn a_bad_line_applies_nothing() {
+        let zones = "zone \"old.example\" {type master; file \"/db\";};\n\n";
+        let (config, path) = setup("bad-line", zones, "add a.example phishing\ndel old.example\nblock b.example\n");
+        match run(&config, Some(&path), false) {
+            Err(Error::InvalidArgument(message)) => assert!(message.starts_with("line 3:"), "{}", message),
+            other => panic!("expected the bad line to be refused, got {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_zones_file(&config).unwrap(), zones);
+        assert!(!config.backend.exists(&config.reason_log));
+    }
+}
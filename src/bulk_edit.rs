@@ -0,0 +1,148 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use chrono::Utc;
+
+use bind_manager::config::Config;
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::{load_reason_log, normalize_domain, read_zone_domains, save_reason_log, DomainEntry};
+
+use crate::summary;
+
+const HEADER: &str = "\
+# Edit the reasons below, one `domain reason` line per blocked domain.
+# Lines starting with # are ignored, and removing a line leaves that domain as it is.
+# Save an empty file, or leave everything unchanged, to abort.
+";
+
+// Reasons are edited one per line, so fold any line breaks or tabs into spaces
+fn one_line(reason: &str) -> String {
+    reason.split(['\n', '\r', '\t']).collect::<Vec<_>>().join(" ")
+}
+
+// Dumps every domain and reason into a temporary file, opens it in $VISUAL or $EDITOR, and applies
+// the edited reasons once the editor exits. Nothing is written if the file has any invalid line.
+// Only the reason log changes, so BIND isn't reloaded.
+pub fn run(config: &Config) -> Result<()> {
+    let mut entries = load_reason_log(config)?;
+    let mut domains: Vec<String> = read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).collect();
+    domains.sort();
+    domains.dedup();
+    let reasons: HashMap<String, &DomainEntry> = entries.iter().map(|entry| (normalize_domain(&entry.domain), entry)).collect();
+
+    let mut original: HashMap<&str, String> = HashMap::new();
+    let mut contents = HEADER.to_string();
+    for domain in &domains {
+        let reason = one_line(reasons.get(domain).map_or(&config.default_reason, |entry| &entry.reason));
+        contents.push_str(&format!("{} {}\n", domain, reason));
+        original.insert(domain, reason);
+    }
+
+    let path = write_temp_file(contents.as_bytes())?;
+    let edited = open_editor(&path).and_then(|()| fs::read_to_string(&path).map_err(Error::reading(&path)));
+    let _ = fs::remove_file(&path);
+    let edited = edited?;
+
+    let mut changes: Vec<(String, String)> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut problems = Vec::new();
+    for (idx, line) in edited.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (domain, reason) = line.split_once(char::is_whitespace).map_or((line, ""), |(domain, reason)| (domain, reason.trim()));
+        let domain = normalize_domain(domain);
+        match original.get(domain.as_str()) {
+            None => problems.push(format!("line {}: {} is not blocked", idx + 1, domain)),
+            Some(_) if !seen.insert(domain.clone()) => problems.push(format!("line {}: {} is listed twice", idx + 1, domain)),
+            Some(_) if reason.is_empty() => problems.push(format!("line {}: {} has no reason", idx + 1, domain)),
+            Some(old) if old != reason => changes.push((domain, reason.to_string())),
+            Some(_) => {}
+        }
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!(" - {}", problem);
+        }
+        return Err(Error::InvalidArgument("The edited reasons have invalid lines, nothing was changed.".to_string()));
+    }
+    if changes.is_empty() {
+        println!("No reasons changed, nothing was written.");
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    for (domain, reason) in &changes {
+        match entries.iter_mut().find(|entry| normalize_domain(&entry.domain) == *domain) {
+            Some(entry) => {
+                println!("{}: {} -> {}", domain, one_line(&entry.reason), reason);
+                entry.reason = reason.clone();
+                entry.reason_updated_at = Some(now);
+            }
+            None => {
+                println!("{}: {} -> {}", domain, config.default_reason, reason);
+                entries.push(DomainEntry { reason: reason.clone(), reason_updated_at: Some(now), ..DomainEntry::new(config, domain) });
+            }
+        }
+        logging::event(Level::Info, "edit", Some(domain), "updated", None);
+    }
+    save_reason_log(config, &entries)?;
+    summary::updated(changes.len());
+    println!("Updated {} {}.", changes.len(), if changes.len() == 1 { "reason" } else { "reasons" });
+    Ok(())
+}
+
+// A new file only we can read, under a name nobody could have guessed and planted a symlink at,
+// since this runs as root in a shared temp dir
+fn write_temp_file(contents: &[u8]) -> Result<PathBuf> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut attempt = 0;
+    loop {
+        // Randomly keyed by the OS, so the name can't be predicted
+        let name = RandomState::new().hash_one(attempt);
+        let path = std::env::temp_dir().join(format!("bind_manager-reasons-{:016x}.txt", name));
+        match options.open(&path) {
+            Ok(mut file) => {
+                file.write_all(contents).map_err(Error::writing(&path))?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 16 => attempt += 1,
+            Err(err) => return Err(Error::writing(&path)(err)),
+        }
+    }
+}
+
+fn open_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL").or_else(|_| std::env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    // Through the shell, so an EDITOR with arguments like `code --wait` works
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .map_err(|err| Error::InvalidArgument(format!("Could not start the editor {}: {}", editor, err)))?;
+    if !status.success() {
+        return Err(Error::InvalidArgument(format!("The editor {} exited with {}, nothing was changed.", editor, status)));
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use bind_manager::config::Config;
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::{atomic, load_reason_log, read_zones_file, save_reason_log_and_zones, DomainEntry};
+
+use crate::reload_bind;
+
+// Bumped whenever the bundle layout changes, so restore can refuse bundles it doesn't understand
+const BUNDLE_VERSION: u32 = 1;
+
+// The zones file and reason log captured together, for moving a list to another server.
+// Files pulled in with include are not part of it.
+#[derive(Serialize, Deserialize)]
+struct Bundle {
+    version: u32,
+    created_at: DateTime<Utc>,
+    zones: String,
+    reason_log: Vec<DomainEntry>,
+}
+
+pub fn backup(config: &Config, path: &Path) -> Result<()> {
+    let bundle = Bundle {
+        version: BUNDLE_VERSION,
+        created_at: Utc::now(),
+        zones: read_zones_file(config)?,
+        reason_log: load_reason_log(config)?,
+    };
+
+    let contents = serde_json::to_vec_pretty(&bundle)?;
+    atomic::write(path, &contents).map_err(Error::writing(path))?;
+    println!("Backed up {} zones file lines and {} reason log entries to {}.", bundle.zones.lines().count(), bundle.reason_log.len(), path.display());
+    logging::event(Level::Info, "backup", None, "written", Some(&path.display().to_string()));
+    Ok(())
+}
+
+pub fn restore(config: &Config, path: &Path) -> Result<()> {
+    // Parse the whole bundle before touching anything, so a bad one leaves the current files alone
+    let contents = fs::read_to_string(path).map_err(Error::reading(path))?;
+    let bundle: Bundle = serde_json::from_str(&contents)
+        .map_err(|err| Error::InvalidArgument(format!("{} is not a bind_manager bundle: {}", path.display(), err)))?;
+    if bundle.version > BUNDLE_VERSION {
+        return Err(Error::InvalidArgument(format!(
+            "{} is a version {} bundle, this bind_manager only understands up to version {}.",
+            path.display(),
+            bundle.version,
+            BUNDLE_VERSION
+        )));
+    }
+
+    save_reason_log_and_zones(config, Some(&bundle.reason_log), Some(bundle.zones.as_bytes()))?;
+
+    println!(
+        "Restored {} reason log entries and the zones file from {} (created {}).",
+        bundle.reason_log.len(),
+        path.display(),
+        bundle.created_at.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    logging::event(Level::Info, "restore", None, "restored", Some(&path.display().to_string()));
+    reload_bind(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use bind_manager::backend::InMemory;
+    use bind_manager::save_reason_log;
+
+    fn entry(domain: &str, reason: &str) -> DomainEntry {
+        DomainEntry { domain: domain.to_string(), reason: reason.to_string(), active: true, ..Default::default() }
+    }
+
+    #[test]
+    fn restore_brings_back_both_files() {
+        let defaults = Config::default();
+        let zones = "zone \"a.example\" {type master; file \"/db\";};\n\n";
+        let backend = Arc::new(InMemory::new().with_file(&defaults.zones_file, zones));
+        let config = Config { no_reload: true, backend, ..defaults };
+        save_reason_log(&config, &[entry("a.example", "phishing")]).unwrap();
+        let reason_log = config.backend.read(&config.reason_log).unwrap();
+        let path = std::env::temp_dir().join(format!("bind_manager-bundle-{}.json", std::process::id()));
+        backup(&config, &path).unwrap();
+
+        config.backend.write(&config.zones_file, b"").unwrap();
+        save_reason_log(&config, &[entry("b.example", "malware")]).unwrap();
+        restore(&config, &path).unwrap();
+        assert_eq!(read_zones_file(&config).unwrap(), zones);
+        assert_eq!(config.backend.read(&config.reason_log).unwrap(), reason_log);
+
+        // A damaged bundle changes nothing
+        fs::write(&path, "{\"version\": 1, \"zones\": ").unwrap();
+        config.backend.write(&config.zones_file, b"").unwrap();
+        assert!(matches!(restore(&config, &path), Err(Error::InvalidArgument(_))));
+        assert_eq!(read_zones_file(&config).unwrap(), "");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,381 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{self, FileBackend};
+use crate::error::{Error, Result};
+use crate::logging::LoggingConfig;
+
+pub const CONFIG_PATH: &str = "/etc/bind/bind_manager.json";
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+pub const ZONES_FILE_PATH: &str = "/etc/bind/blacklisted.zones";
+pub const REASON_LOG_PATH: &str = "/etc/bind/reason_log.json";
+pub const HOSTS_FILE_PATH: &str = "/etc/bind/blocked.hosts";
+pub const HISTORY_DIR_PATH: &str = "/etc/bind/bind_manager.history";
+pub const BLACKLIST_ZONE: &str = "/etc/bind/zones/master/blockeddomains.db";
+pub const DEFAULT_REASON: &str = "No reason provided.";
+pub const DEFAULT_RELOAD_COMMAND: &str = "rndc reload";
+pub const DEFAULT_RELOAD_TIMEOUT_SECS: u64 = 30;
+pub const DEFAULT_ZONE_TEMPLATE: &str = "zone \"{domain}\" {type master; file \"{file}\";};";
+pub const FORWARD_ZONE_TEMPLATE: &str = "zone \"{domain}\" {type forward; forward only; forwarders { {forwarders} };};";
+pub const HOSTS_TEMPLATE: &str = "0.0.0.0 {domain}";
+pub const DEFAULT_PRUNE_MAX_PERCENT: usize = 20;
+// Infrastructure that blocking would take half the network down with, protected on top of protected_domains
+pub const DEFAULT_PROTECTED_DOMAINS: &[&str] = &[
+    "localhost",
+    "in-addr.arpa",
+    "ip6.arpa",
+    "root-servers.net",
+    "gtld-servers.net",
+    "windowsupdate.com",
+    "update.microsoft.com",
+    "deb.debian.org",
+    "security.debian.org",
+    "archive.ubuntu.com",
+    "security.ubuntu.com",
+    "cloudflare.com",
+    "akamaiedge.net",
+    "fastly.net",
+    "cloudfront.net",
+];
+
+// Blocklist sinkholes the listed domains; allowlist sinkholes everything except the listed domains
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Blocklist,
+    Allowlist,
+}
+
+// How blocked domains are sinkholed: a master zone answering from zone_db, or forwarding to a sinkhole resolver
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoneType {
+    #[default]
+    Master,
+    Forward,
+}
+
+// What the blocks are written as: zone statements, hosts-style `0.0.0.0 domain` lines instead of
+// them, or zone statements plus a hosts file generated from them
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Zones,
+    Hosts,
+    Both,
+}
+
+// Whether a domain whose zone is only in a commented-out block of the zones file counts as listed
+// when it is added: absent adds a fresh active zone, present warns and leaves it commented out
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentedZones {
+    #[default]
+    Absent,
+    Present,
+}
+
+// How the reason log is stored: a JSON array, one JSON object per line, or TOML
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasonLogFormat {
+    #[default]
+    Json,
+    Jsonl,
+    Toml,
+}
+
+impl ReasonLogFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasonLogFormat::Json => "json",
+            ReasonLogFormat::Jsonl => "jsonl",
+            ReasonLogFormat::Toml => "toml",
+        }
+    }
+}
+
+impl ZoneType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ZoneType::Master => "master",
+            ZoneType::Forward => "forward",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub zones_file: PathBuf,
+    pub reason_log: PathBuf,
+    // Detected from the reason log itself when not set
+    pub reason_log_format: Option<ReasonLogFormat>,
+    pub mode: Mode,
+    // The upstream resolvers allowed domains are forwarded to in allowlist mode, or the sinkhole
+    // resolvers blocked domains are forwarded to with zone_type forward
+    pub forwarders: Vec<String>,
+    // The zone type written for blocked domains in blocklist mode
+    pub zone_type: ZoneType,
+    pub output: OutputFormat,
+    // The hosts file generated alongside the zones with output both
+    pub hosts_file: PathBuf,
+    // Domains `add` refuses to block (or block a parent of) without --force, on top of the defaults
+    pub protected_domains: Vec<String>,
+    // Our own domain, also protected; read from /etc/resolv.conf when not set
+    pub local_domain: Option<String>,
+    // A reference list of widely used legitimate domains (e.g. a top-1M list, one domain or
+    // `rank,domain` per line) that `add` asks to --force before blocking
+    pub known_good_list: Option<PathBuf>,
+    // Where `check` keeps a prebuilt copy of the parsed zones, rebuilt when the zones files change
+    pub zone_cache: Option<PathBuf>,
+    // Set to false to skip the known_good_list check without removing it from the config
+    pub check_known_good: bool,
+    // `import --prune` refuses to remove more than this percentage of the list without --force
+    pub prune_max_percent: usize,
+    // The allowed reason categories for `add --category`
+    pub categories: Vec<String>,
+    // The zone db file that blocked domains point at, unless overridden per domain
+    pub zone_db: String,
+    // The logging/monitoring zone db file that soft blocks (`add --mode log`) point at
+    pub log_zone_db: Option<String>,
+    pub commented_zones: CommentedZones,
+    // The comment kept at the top of the zones file, none unless configured
+    pub header: Option<String>,
+    // The single-line zone statement written for each domain, with {domain}, {file} and {forwarders} substituted
+    pub zone_template: Option<String>,
+    // The command run to make BIND pick up changes, split on whitespace
+    pub reload_command: String,
+    // Never reload BIND, for when something else takes care of it
+    pub no_reload: bool,
+    // A change only stands if BIND accepted it: a failed (or skipped) reload rolls the files back
+    pub strict_reload: bool,
+    // How long the reload command may run before it is killed, 0 waits forever
+    pub reload_timeout_secs: u64,
+    // Reloads within this many seconds of the previous one are skipped and left pending, 0 disables this
+    pub reload_debounce_secs: u64,
+    // Stored when `add` is given no reason, and shown for zones without a reason log entry
+    pub default_reason: String,
+    // Refuse every change, for handing the tool to people who should only inspect the list
+    pub read_only: bool,
+    // The profile used when --profile isn't given
+    pub profile: Option<String>,
+    pub profiles: HashMap<String, Profile>,
+    pub logging: LoggingConfig,
+    // Where every change is recorded, along with the snapshots undo restores
+    pub history_dir: PathBuf,
+    // Where the zones file, reason log and hosts file are read and written, the real files unless
+    // replaced (e.g. with an in-memory backend for tests)
+    #[serde(skip)]
+    pub backend: Arc<dyn FileBackend>,
+}
+
+// A named set of overrides, so one config can manage several independent lists
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Profile {
+    pub zones_file: Option<PathBuf>,
+    pub reason_log: Option<PathBuf>,
+    pub mode: Option<Mode>,
+    pub forwarders: Option<Vec<String>>,
+    pub zone_type: Option<ZoneType>,
+    pub zone_db: Option<String>,
+    pub zone_template: Option<String>,
+    // e.g. `rndc reload guest-blocklist`, to reload only the view or zone this profile's list is in
+    pub reload_command: Option<String>,
+    pub read_only: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            zones_file: PathBuf::from(ZONES_FILE_PATH),
+            reason_log: PathBuf::from(REASON_LOG_PATH),
+            reason_log_format: None,
+            mode: Mode::Blocklist,
+            forwarders: Vec::new(),
+            zone_type: ZoneType::Master,
+            output: OutputFormat::Zones,
+            hosts_file: PathBuf::from(HOSTS_FILE_PATH),
+            protected_domains: Vec::new(),
+            local_domain: None,
+            known_good_list: None,
+            zone_cache: None,
+            check_known_good: true,
+            prune_max_percent: DEFAULT_PRUNE_MAX_PERCENT,
+            categories: Vec::new(),
+            zone_db: BLACKLIST_ZONE.to_string(),
+            log_zone_db: None,
+            commented_zones: CommentedZones::Absent,
+            header: None,
+            zone_template: None,
+            reload_command: DEFAULT_RELOAD_COMMAND.to_string(),
+            no_reload: false,
+            strict_reload: false,
+            reload_timeout_secs: DEFAULT_RELOAD_TIMEOUT_SECS,
+            reload_debounce_secs: 0,
+            default_reason: DEFAULT_REASON.to_string(),
+            read_only: false,
+            profile: None,
+            profiles: HashMap::new(),
+            logging: LoggingConfig::default(),
+            history_dir: PathBuf::from(HISTORY_DIR_PATH),
+            backend: backend::filesystem(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(profile: Option<&str>) -> Result<Config> {
+        let mut config = if Path::new(CONFIG_PATH).exists() {
+            let contents = fs::read_to_string(CONFIG_PATH).map_err(Error::reading(Path::new(CONFIG_PATH)))?;
+            serde_json::from_str(&contents).map_err(|err| Error::Config(format!("Invalid config file {}: {}", CONFIG_PATH, err)))?
+        } else {
+            Config::default()
+        };
+
+        if let Some(name) = profile.map(str::to_string).or_else(|| config.profile.clone()) {
+            config.apply_profile(&name)?;
+        }
+        Ok(config)
+    }
+
+    fn apply_profile(&mut self, name: &str) -> Result<()> {
+        let profile = match self.profiles.remove(name) {
+            Some(profile) => profile,
+            None => return Err(Error::InvalidArgument(format!("Unknown profile {}, it is not defined in {}.", name, CONFIG_PATH))),
+        };
+
+        if let Some(zones_file) = profile.zones_file {
+            self.zones_file = zones_file;
+        }
+        if let Some(reason_log) = profile.reason_log {
+            self.reason_log = reason_log;
+        }
+        if let Some(mode) = profile.mode {
+            self.mode = mode;
+        }
+        if let Some(forwarders) = profile.forwarders {
+            self.forwarders = forwarders;
+        }
+        if let Some(zone_type) = profile.zone_type {
+            self.zone_type = zone_type;
+        }
+        if let Some(zone_db) = profile.zone_db {
+            self.zone_db = zone_db;
+        }
+        if profile.zone_template.is_some() {
+            self.zone_template = profile.zone_template;
+        }
+        if let Some(reload_command) = profile.reload_command {
+            self.reload_command = reload_command;
+        }
+        if let Some(read_only) = profile.read_only {
+            self.read_only = read_only;
+        }
+        self.profile = Some(name.to_string());
+        Ok(())
+    }
+
+    // Whether listed domains get forward zones rather than master zones pointing at a db file
+    pub fn forwards_listed(&self) -> bool {
+        self.mode == Mode::Allowlist || self.zone_type == ZoneType::Forward
+    }
+
+    pub fn zone_template(&self) -> &str {
+        match (&self.zone_template, self.mode) {
+            (Some(template), _) => template,
+            (None, _) if self.output == OutputFormat::Hosts => HOSTS_TEMPLATE,
+            (None, Mode::Blocklist) if self.zone_type == ZoneType::Forward => FORWARD_ZONE_TEMPLATE,
+            (None, Mode::Blocklist) => DEFAULT_ZONE_TEMPLATE,
+            (None, Mode::Allowlist) => FORWARD_ZONE_TEMPLATE,
+        }
+    }
+
+    pub fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(Error::ReadOnly("Refusing to change anything, bind_manager is in read-only mode.".to_string()));
+        }
+        Ok(())
+    }
+
+    // The built-in protected domains, the configured ones and the local domain
+    pub fn protected_domains(&self) -> Vec<String> {
+        let mut protected: Vec<String> = DEFAULT_PROTECTED_DOMAINS.iter().map(|domain| domain.to_string()).collect();
+        protected.extend(self.protected_domains.iter().cloned());
+        protected.extend(self.local_domain().map(|(domain, _)| domain));
+        protected
+    }
+
+    // Our own domain and where it came from, for saying so when it stops a block
+    pub fn local_domain(&self) -> Option<(String, String)> {
+        match &self.local_domain {
+            Some(domain) => Some((domain.clone(), format!("local_domain in {}", CONFIG_PATH))),
+            None => resolv_conf_domain(&fs::read_to_string(RESOLV_CONF_PATH).ok()?)
+                .map(|(domain, directive)| (domain, format!("the {} line of {}", directive, RESOLV_CONF_PATH))),
+        }
+    }
+
+    // What the managed list is called in messages
+    pub fn list_name(&self) -> &'static str {
+        match self.mode {
+            Mode::Blocklist => "blacklist",
+            Mode::Allowlist => "allowlist",
+        }
+    }
+
+    // Which list reloads are tracked for in the state file. Profiles and --zones-file each get their
+    // own, so one list's reload neither holds back another's nor clears it as pending.
+    pub fn reload_key(&self) -> String {
+        format!("{}:{}", self.profile.as_deref().unwrap_or(""), self.zones_file.display())
+    }
+
+    // Returns the configured spelling of a category, matching case-insensitively
+    pub fn find_category(&self, category: &str) -> Option<&str> {
+        self.categories
+            .iter()
+            .find(|allowed| allowed.eq_ignore_ascii_case(category.trim()))
+            .map(String::as_str)
+    }
+}
+
+// The `domain` entry of resolv.conf, or without one the first `search` entry, with the directive
+// it came from. The rest of the search list is only where short names are looked up, not ours.
+fn resolv_conf_domain(contents: &str) -> Option<(String, &'static str)> {
+    let value = |key: &str| {
+        contents.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next() == Some(key)).then(|| words.next()).flatten().map(str::to_string)
+        })
+    };
+    let found = match value("domain") {
+        Some(domain) => Some((domain, "domain")),
+        None => value("search").map(|domain| (domain, "search")),
+    };
+    found.filter(|(domain, _)| domain != ".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_domain_or_first_search_entry_is_local() {
+        let both = "search corp.example lab.example\nnameserver 10.0.0.1\ndomain example.org\n";
+        assert_eq!(resolv_conf_domain(both), Some(("example.org".to_string(), "domain")));
+        let search = "nameserver 10.0.0.1\nsearch corp.example lab.example\n";
+        assert_eq!(resolv_conf_domain(search), Some(("corp.example".to_string(), "search")));
+        assert_eq!(resolv_conf_domain("search .\n"), None);
+        assert_eq!(resolv_conf_domain("nameserver 10.0.0.1\n"), None);
+    }
+}
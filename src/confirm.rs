@@ -0,0 +1,94 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::HashSet;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use bind_manager::error::{Error, Result};
+
+// Up to this many domains are listed in full before asking, beyond it only a summary is shown
+const LIST_IN_FULL: usize = 20;
+const PAGE_SIZE: usize = 20;
+
+fn read_answer(question: &str) -> Result<String> {
+    print!("{} ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+fn prompt(question: &str) -> Result<String> {
+    Ok(read_answer(question)?.to_ascii_lowercase())
+}
+
+fn page(domains: &[String]) -> Result<()> {
+    for (idx, chunk) in domains.chunks(PAGE_SIZE).enumerate() {
+        for domain in chunk {
+            println!(" - {}", domain);
+        }
+        let shown = idx * PAGE_SIZE + chunk.len();
+        if shown < domains.len() && prompt(&format!("-- {} of {}, Enter for more, q to stop --", shown, domains.len()))? == "q" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Asks a yes/no question, returning whether to go ahead. `yes` skips it, and without a terminal
+// to ask on it has to be given.
+pub fn confirm(question: &str, yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Error::InvalidArgument(format!("{} Refusing without confirmation, pass --yes.", question)));
+    }
+    Ok(prompt(&format!("{} Continue? [y/N]", question))? == "y")
+}
+
+// Asks for the reason a domain is blocked when one wasn't given, so blocks get documented. Returns
+// None without a terminal to ask on, or when the answer is left empty.
+pub fn ask_reason(domain: &str) -> Result<Option<String>> {
+    if !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let reason = read_answer(&format!("Reason for blocking {}:", domain))?;
+    Ok(Some(reason).filter(|reason| !reason.is_empty()))
+}
+
+// Asks before removing `domains`, returning whether to go ahead. `yes` skips the question, and
+// without a terminal to ask on it has to be given.
+pub fn confirm_removal(domains: &[String], yes: bool) -> Result<bool> {
+    if yes || domains.is_empty() {
+        return Ok(true);
+    }
+    if !io::stdin().is_terminal() {
+        return Err(Error::InvalidArgument(format!("Refusing to remove {} domains without confirmation, pass --yes.", domains.len())));
+    }
+
+    if domains.len() <= LIST_IN_FULL {
+        println!("This will remove {} {}:", domains.len(), if domains.len() == 1 { "domain" } else { "domains" });
+        for domain in domains {
+            println!(" - {}", domain);
+        }
+        return Ok(prompt("Continue? [y/N]")? == "y");
+    }
+
+    let tlds: HashSet<&str> = domains.iter().map(|domain| domain.rsplit('.').next().unwrap_or(domain)).collect();
+    loop {
+        let summary = format!(
+            "This will remove {} domains across {} {}. Continue? [y/N/list]",
+            domains.len(),
+            tlds.len(),
+            if tlds.len() == 1 { "TLD" } else { "TLDs" }
+        );
+        match prompt(&summary)?.as_str() {
+            "y" | "yes" => return Ok(true),
+            "l" | "list" => page(domains)?,
+            _ => return Ok(false),
+        }
+    }
+}
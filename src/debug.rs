@@ -0,0 +1,38 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// Timing and diagnostics for troubleshooting slow runs, printed to stderr with --debug so the
+// normal output is unchanged.
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn log(message: impl Display) {
+    if enabled() {
+        eprintln!("debug: {}", message);
+    }
+}
+
+// Runs one phase of the work, reporting how long it took
+pub fn timed<T>(phase: impl Display, work: impl FnOnce() -> T) -> T {
+    if !enabled() {
+        return work();
+    }
+    let start = Instant::now();
+    let result = work();
+    eprintln!("debug: {} took {:.1?}", phase, start.elapsed());
+    result
+}
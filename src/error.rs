@@ -0,0 +1,111 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::CONFIG_PATH;
+
+// Every failure maps onto a documented exit code:
+//   0 success, 1 runtime/IO/reload failure, 2 invalid arguments or domain or a refused write, 3 not found
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    File { path: PathBuf, writing: bool, source: io::Error },
+    Config(String),
+    Reload(String),
+    InvalidArgument(String),
+    // A change was attempted while read_only is set
+    ReadOnly(String),
+    NotFound(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub const EXIT_FAILURE: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+pub const EXIT_NOT_FOUND: u8 = 3;
+
+impl Error {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io(_) | Error::File { .. } | Error::Config(_) | Error::Reload(_) => EXIT_FAILURE,
+            Error::InvalidArgument(_) | Error::ReadOnly(_) => EXIT_USAGE,
+            Error::NotFound(_) => EXIT_NOT_FOUND,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::File { path, writing, source } => {
+                let action = if *writing { "writing" } else { "reading" };
+                match source.kind() {
+                    io::ErrorKind::PermissionDenied => {
+                        write!(f, "Permission denied {} {} — try running with sudo.", action, path.display())
+                    }
+                    io::ErrorKind::NotFound => write!(f, "{} does not exist.", path.display()),
+                    io::ErrorKind::ReadOnlyFilesystem => {
+                        write!(f, "Cannot write {}, it is on a read-only filesystem.", path.display())
+                    }
+                    _ => write!(f, "Failed {} {}: {}", action, path.display(), source),
+                }
+            }
+            Error::Config(message)
+            | Error::Reload(message)
+            | Error::InvalidArgument(message)
+            | Error::ReadOnly(message)
+            | Error::NotFound(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    // For use with map_err, attaching the path to an IO error so the message can say what failed
+    pub fn reading(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| misconfigured(path, &source).unwrap_or(Error::File { path: path.to_path_buf(), writing: false, source })
+    }
+
+    pub fn writing(path: &Path) -> impl FnOnce(io::Error) -> Error + '_ {
+        move |source| misconfigured(path, &source).unwrap_or(Error::File { path: path.to_path_buf(), writing: true, source })
+    }
+}
+
+// A configured path that is a directory or a dangling symlink, which the plain IO error explains badly
+fn misconfigured(path: &Path, source: &io::Error) -> Option<Error> {
+    match source.kind() {
+        io::ErrorKind::IsADirectory => {
+            Some(Error::Config(format!("{} is a directory, not a file. Check the path in {}.", path.display(), CONFIG_PATH)))
+        }
+        io::ErrorKind::NotFound => {
+            let target = fs::read_link(path).ok()?;
+            Some(Error::Config(format!(
+                "{} is a dangling symlink to {}, which does not exist. Fix the link or point {} at the real file.",
+                path.display(),
+                target.display(),
+                CONFIG_PATH
+            )))
+        }
+        _ => None,
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Io(err.into())
+    }
+}
@@ -0,0 +1,133 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// Writes the blocked domains out for downstream resolvers, whole or one file per TLD, or as a
+// snapshot that read-only replicas answer `check` and the other read commands from.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use bind_manager::backend::InMemory;
+use bind_manager::config::{Config, Mode, ReasonLogFormat};
+use bind_manager::debug;
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::zones::{self, parse_domain_from_line};
+use bind_manager::{atomic, entry_zone_file, load_reason_log, normalize_domain, read_zone_domains, render_zone, DomainEntry};
+
+use crate::tld_of;
+
+pub const FORMATS: &[&str] = &["domains", "hosts", "zones"];
+
+// The blocked domains in `format`, to stdout (or --output), or with an output dir one `<tld>.<format>` file per TLD
+pub fn run(config: &Config, format: &str, output_dir: Option<&Path>) -> Result<()> {
+    let mut domains: Vec<String> = read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).collect();
+    domains.sort();
+    domains.dedup();
+    let entries = load_reason_log(config)?;
+    let by_domain: HashMap<String, &DomainEntry> = entries.iter().map(|entry| (normalize_domain(&entry.domain), entry)).collect();
+
+    let Some(dir) = output_dir else {
+        for line in render(config, format, &domains, &by_domain)?.lines() {
+            out!("{}", line);
+        }
+        return Ok(());
+    };
+
+    let mut by_tld: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for domain in &domains {
+        by_tld.entry(tld_of(domain)).or_default().push(domain.clone());
+    }
+    fs::create_dir_all(dir).map_err(Error::writing(dir))?;
+    for (tld, domains) in &by_tld {
+        let path = dir.join(format!("{}.{}", tld, format));
+        atomic::write(&path, render(config, format, domains, &by_domain)?.as_bytes()).map_err(Error::writing(&path))?;
+        println!("{}: {} {}", path.display(), domains.len(), if domains.len() == 1 { "domain" } else { "domains" });
+    }
+    println!("Exported {} domains into {} TLD files in {}.", domains.len(), by_tld.len(), dir.display());
+    logging::event(Level::Info, "export", None, "written", Some(&dir.display().to_string()));
+    Ok(())
+}
+
+// Bumped whenever the snapshot layout changes, so replicas refuse snapshots they don't understand
+const SNAPSHOT_VERSION: u32 = 1;
+
+// The whole list at one point in time: every zone statement, flattened out of the includes, and the reason log
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    created_at: DateTime<Utc>,
+    mode: Mode,
+    zones: Vec<String>,
+    reason_log: Vec<DomainEntry>,
+}
+
+pub fn write_snapshot(config: &Config, path: &Path) -> Result<()> {
+    let sources = zones::read_sources(config.backend.as_ref(), &config.zones_file)?;
+    let zones: Vec<String> = sources
+        .iter()
+        .flat_map(|source| source.contents.lines())
+        .filter(|line| parse_domain_from_line(line).is_some())
+        .map(str::to_string)
+        .collect();
+    let snapshot = Snapshot { version: SNAPSHOT_VERSION, created_at: Utc::now(), mode: config.mode, zones, reason_log: load_reason_log(config)? };
+
+    atomic::write(path, &serde_json::to_vec(&snapshot)?).map_err(Error::writing(path))?;
+    println!("Wrote a snapshot of {} zones and {} reason log entries to {}.", snapshot.zones.len(), snapshot.reason_log.len(), path.display());
+    logging::event(Level::Info, "export", None, "snapshot", Some(&path.display().to_string()));
+    Ok(())
+}
+
+// Serves the snapshot instead of the real files, read-only since there is nothing to write back to
+pub fn load_snapshot(config: &mut Config, path: &Path) -> Result<()> {
+    let contents = fs::read(path).map_err(Error::reading(path))?;
+    let snapshot: Snapshot = serde_json::from_slice(&contents)
+        .map_err(|err| Error::InvalidArgument(format!("{} is not a bind_manager snapshot: {}", path.display(), err)))?;
+    if snapshot.version > SNAPSHOT_VERSION {
+        return Err(Error::InvalidArgument(format!(
+            "{} is a version {} snapshot, this bind_manager only understands up to version {}.",
+            path.display(),
+            snapshot.version,
+            SNAPSHOT_VERSION
+        )));
+    }
+
+    debug::log(format_args!("answering from the snapshot {}, taken {}", path.display(), snapshot.created_at.to_rfc3339()));
+    let mut zones = snapshot.zones.join("\n");
+    zones.push('\n');
+    let backend = InMemory::new()
+        .with_file(&config.zones_file, zones)
+        .with_file(&config.reason_log, ReasonLogFormat::Json.encode(&snapshot.reason_log)?);
+    config.backend = Arc::new(backend);
+    config.reason_log_format = Some(ReasonLogFormat::Json);
+    config.mode = snapshot.mode;
+    config.zone_cache = None;
+    config.read_only = true;
+    Ok(())
+}
+
+fn render(config: &Config, format: &str, domains: &[String], by_domain: &HashMap<String, &DomainEntry>) -> Result<String> {
+    let mut contents = String::new();
+    for domain in domains {
+        let line = match format {
+            "hosts" => format!("0.0.0.0 {}", domain),
+            "zones" => {
+                let zone_file = match by_domain.get(domain) {
+                    Some(entry) => entry_zone_file(config, entry)?,
+                    None => None,
+                };
+                render_zone(config, domain, zone_file)?
+            }
+            _ => domain.clone(),
+        };
+        contents.push_str(&line);
+        contents.push('\n');
+    }
+    Ok(contents)
+}
@@ -0,0 +1,491 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use bind_manager::atomic;
+use bind_manager::backend::{FileBackend, Filesystem};
+use bind_manager::config::{Config, ReasonLogFormat};
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::zones::parse_domain_from_line;
+use bind_manager::{normalize_domain, DomainEntry};
+
+use crate::reload_bind;
+
+const AUDIT_LOG: &str = "audit.jsonl";
+// Only this many of the most recent operations keep the snapshot needed to undo them
+const KEEP_SNAPSHOTS: usize = 50;
+
+// Every change made through bind_manager, one JSON object per line of the audit log. The files as
+// they were before the change are kept in a snapshot next to it, which is what undo restores.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Operation {
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    // The subcommand, e.g. add or del
+    #[serde(default)]
+    pub action: String,
+    pub command: String,
+    // The domains whose zone or reason log entry the operation changed
+    #[serde(default)]
+    pub domains: Vec<String>,
+    pub zones_file: PathBuf,
+    pub before: Fingerprint,
+    pub after: Fingerprint,
+    // For an undo, the operations it reverted. They stay in the log, this is what marks them undone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub undoes: Vec<u64>,
+}
+
+// Hashes of both files, to tell whether someone edited them outside bind_manager in between
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub zones: String,
+    pub reason_log: String,
+}
+
+// The raw contents of both files, None where the file didn't exist
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(into = "StoredSnapshot", try_from = "StoredSnapshot")]
+pub struct Snapshot {
+    zones: Option<Vec<u8>>,
+    reason_log: Option<Vec<u8>>,
+}
+
+// A snapshot as kept on disk. The contents are base64, so undo puts back exactly the bytes there
+// were even if they aren't UTF-8; snapshots from before that have them as plain text.
+#[derive(Serialize, Deserialize)]
+struct StoredSnapshot {
+    #[serde(default)]
+    encoding: Encoding,
+    zones: Option<String>,
+    reason_log: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum Encoding {
+    #[default]
+    Text,
+    Base64,
+}
+
+impl From<Snapshot> for StoredSnapshot {
+    fn from(snapshot: Snapshot) -> StoredSnapshot {
+        let encode = |contents: Option<Vec<u8>>| contents.map(|contents| BASE64.encode(contents));
+        StoredSnapshot { encoding: Encoding::Base64, zones: encode(snapshot.zones), reason_log: encode(snapshot.reason_log) }
+    }
+}
+
+impl TryFrom<StoredSnapshot> for Snapshot {
+    type Error = base64::DecodeError;
+
+    fn try_from(stored: StoredSnapshot) -> std::result::Result<Snapshot, base64::DecodeError> {
+        let decode = |contents: Option<String>| match stored.encoding {
+            Encoding::Text => Ok(contents.map(String::into_bytes)),
+            Encoding::Base64 => contents.map(|contents| BASE64.decode(contents)).transpose(),
+        };
+        Ok(Snapshot { zones: decode(stored.zones)?, reason_log: decode(stored.reason_log)? })
+    }
+}
+
+impl Snapshot {
+    pub fn capture(config: &Config) -> Result<Snapshot> {
+        let backend = config.backend.as_ref();
+        Ok(Snapshot { zones: read_optional(backend, &config.zones_file)?, reason_log: read_optional(backend, &config.reason_log)? })
+    }
+
+    // Domains that have a zone in one snapshot but not the other, or a different reason log entry
+    fn changed_domains(&self, other: &Snapshot) -> Vec<String> {
+        let zones = |snapshot: &Snapshot| -> HashSet<String> {
+            let zones = snapshot.zones.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+            zones.lines().filter_map(parse_domain_from_line).map(|domain| normalize_domain(&domain)).collect()
+        };
+        let entries = |snapshot: &Snapshot| -> HashMap<String, String> {
+            let entries: Vec<DomainEntry> = snapshot
+                .reason_log
+                .as_deref()
+                .and_then(|log| ReasonLogFormat::detect(log)?.decode(log).ok())
+                .unwrap_or_default();
+            entries.iter().map(|entry| (normalize_domain(&entry.domain), serde_json::to_string(entry).unwrap_or_default())).collect()
+        };
+
+        let (zones_before, zones_after) = (zones(self), zones(other));
+        let (entries_before, entries_after) = (entries(self), entries(other));
+        let mut changed: Vec<String> = zones_before.symmetric_difference(&zones_after).cloned().collect();
+        for domain in entries_before.keys().chain(entries_after.keys()) {
+            if entries_before.get(domain) != entries_after.get(domain) {
+                changed.push(domain.clone());
+            }
+        }
+        changed.sort();
+        changed.dedup();
+        changed
+    }
+
+    // Puts both files back the way they were captured
+    pub fn restore(&self, config: &Config) -> Result<()> {
+        let backend = config.backend.as_ref();
+        restore_file(backend, &config.zones_file, self.zones.as_deref())?;
+        restore_file(backend, &config.reason_log, self.reason_log.as_deref())
+    }
+
+    fn fingerprint(&self) -> Fingerprint {
+        let hash = |contents: &Option<Vec<u8>>| match contents {
+            Some(contents) => format!("{:x}", Sha256::digest(contents)),
+            None => "missing".to_string(),
+        };
+        Fingerprint { zones: hash(&self.zones), reason_log: hash(&self.reason_log) }
+    }
+}
+
+// The history itself always lives on the real filesystem, the files it snapshots in `config.backend`
+fn read_optional(backend: &dyn FileBackend, path: &Path) -> Result<Option<Vec<u8>>> {
+    match backend.read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(Error::reading(path)(err)),
+    }
+}
+
+fn audit_log_path(config: &Config) -> PathBuf {
+    config.history_dir.join(AUDIT_LOG)
+}
+
+fn snapshot_path(config: &Config, seq: u64) -> PathBuf {
+    config.history_dir.join(format!("{}.json", seq))
+}
+
+fn load_operations(config: &Config) -> Result<Vec<Operation>> {
+    let path = audit_log_path(config);
+    let contents = match read_optional(&Filesystem, &path)? {
+        Some(contents) => contents,
+        None => return Ok(Vec::new()),
+    };
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(|err| Error::Config(format!("Invalid line in {}: {}", path.display(), err))))
+        .collect()
+}
+
+// The audit log is only ever appended to, earlier operations are never rewritten
+fn append_operation(config: &Config, operation: &Operation) -> Result<()> {
+    let dir = config.history_dir.as_path();
+    fs::create_dir_all(dir).map_err(Error::writing(dir))?;
+    let path = audit_log_path(config);
+    let mut line = serde_json::to_string(operation)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().append(true).create(true).open(&path).map_err(Error::writing(&path))?;
+    file.write_all(line.as_bytes()).map_err(Error::writing(&path))
+}
+
+fn next_seq(config: &Config) -> Result<u64> {
+    Ok(load_operations(config)?.last().map_or(1, |operation| operation.seq + 1))
+}
+
+// Appends an operation if the command actually changed either file, keeping `before` so it can be undone
+pub fn record(config: &Config, action: &str, command: &str, before: Snapshot) -> Result<()> {
+    let after_snapshot = Snapshot::capture(config)?;
+    let after = after_snapshot.fingerprint();
+    let before_fingerprint = before.fingerprint();
+    if after == before_fingerprint {
+        return Ok(());
+    }
+
+    let dir = config.history_dir.as_path();
+    fs::create_dir_all(dir).map_err(Error::writing(dir))?;
+    let seq = next_seq(config)?;
+    let path = snapshot_path(config, seq);
+    atomic::write(&path, &serde_json::to_vec(&before)?).map_err(Error::writing(&path))?;
+
+    append_operation(config, &Operation {
+        seq,
+        at: Utc::now(),
+        action: action.to_string(),
+        command: command.to_string(),
+        domains: before.changed_domains(&after_snapshot),
+        zones_file: config.zones_file.clone(),
+        before: before_fingerprint,
+        after,
+        undoes: Vec::new(),
+    })?;
+
+    // Older operations stay in the audit log, they just can't be undone any more
+    if let Some(expired) = seq.checked_sub(KEEP_SNAPSHOTS as u64) {
+        let _ = fs::remove_file(snapshot_path(config, expired));
+    }
+    Ok(())
+}
+
+pub struct AuditFilter {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    domain: Option<String>,
+    action: Option<String>,
+}
+
+impl AuditFilter {
+    pub fn parse(since: Option<&str>, until: Option<&str>, domain: Option<&str>, action: Option<String>) -> Result<AuditFilter> {
+        Ok(AuditFilter {
+            since: since.map(|since| parse_time(since, NaiveTime::MIN)).transpose()?,
+            until: until.map(|until| parse_time(until, NaiveTime::from_hms_opt(23, 59, 59).unwrap_or_default())).transpose()?,
+            domain: domain.map(normalize_domain),
+            action: action.map(|action| action.to_ascii_lowercase()),
+        })
+    }
+
+    fn matches(&self, operation: &Operation) -> bool {
+        self.since.is_none_or(|since| operation.at >= since)
+            && self.until.is_none_or(|until| operation.at <= until)
+            && self.domain.as_ref().is_none_or(|domain| operation.domains.contains(domain))
+            && self.action.as_ref().is_none_or(|action| operation.action == *action)
+    }
+}
+
+// A bare date covers the whole day, so it is taken at `time` (the start for since, the end for until)
+fn parse_time(value: &str, time: NaiveTime) -> Result<DateTime<Utc>> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Ok(at.with_timezone(&Utc));
+    }
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => Ok(date.and_time(time).and_utc()),
+        Err(_) => Err(Error::InvalidArgument(format!("Invalid date {}, expected YYYY-MM-DD or an RFC 3339 time.", value))),
+    }
+}
+
+// Every recorded operation matching the filter, newest first unless oldest_first. The audit log
+// is streamed line by line, only the matches are kept.
+pub fn audit(config: &Config, filter: &AuditFilter, oldest_first: bool, json: bool) -> Result<()> {
+    let path = audit_log_path(config);
+    let file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            out!("No operations recorded yet.");
+            return Ok(());
+        }
+        Err(err) => return Err(Error::reading(&path)(err)),
+    };
+
+    let mut matched = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(Error::reading(&path))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let operation: Operation = serde_json::from_str(&line).map_err(|err| Error::Config(format!("Invalid line in {}: {}", path.display(), err)))?;
+        if filter.matches(&operation) {
+            matched.push(operation);
+        }
+    }
+    if !oldest_first {
+        matched.reverse();
+    }
+
+    if json {
+        out!("{}", serde_json::to_string_pretty(&matched)?);
+        return Ok(());
+    }
+    if matched.is_empty() {
+        out!("No matching operations.");
+    }
+    for operation in &matched {
+        out!("{}  {:<10} {}", operation.at.format("%Y-%m-%d %H:%M:%S UTC"), operation.action, operation.command);
+        if !operation.domains.is_empty() {
+            out!("    changed: {}", operation.domains.join(", "));
+        }
+    }
+    Ok(())
+}
+
+// This list's operations that can still be undone, oldest first: not undos themselves, and not
+// already reverted by one
+fn own_operations(config: &Config) -> Result<Vec<Operation>> {
+    let operations: Vec<Operation> = load_operations(config)?.into_iter().filter(|operation| operation.zones_file == config.zones_file).collect();
+    let undone: HashSet<u64> = operations.iter().flat_map(|operation| operation.undoes.iter().copied()).collect();
+    Ok(operations.into_iter().filter(|operation| operation.undoes.is_empty() && !undone.contains(&operation.seq)).collect())
+}
+
+pub fn show(config: &Config, limit: usize) -> Result<()> {
+    let operations = own_operations(config)?;
+    if operations.is_empty() {
+        out!("No operations to undo.");
+        return Ok(());
+    }
+
+    out!("Recent operations, most recent first (undo --steps N reverts the first N):");
+    for (idx, operation) in operations.iter().rev().take(limit).enumerate() {
+        let undoable = if snapshot_path(config, operation.seq).exists() { "" } else { "  (too old to undo)" };
+        out!("{:>4}  {}  {}{}", idx + 1, operation.at.format("%Y-%m-%d %H:%M:%S UTC"), operation.command, undoable);
+    }
+    Ok(())
+}
+
+// Reverts the last `steps` operations by restoring the files as they were before the earliest of
+// them. The undo is recorded as an operation of its own, the ones it reverted stay in the log.
+pub fn undo(config: &Config, steps: usize) -> Result<()> {
+    config.check_writable()?;
+    let operations = own_operations(config)?;
+    if steps == 0 || steps > operations.len() {
+        return Err(Error::InvalidArgument(format!(
+            "Can't undo {} {}, {} recorded.",
+            steps,
+            if steps == 1 { "operation" } else { "operations" },
+            operations.len()
+        )));
+    }
+    let undone = &operations[operations.len() - steps..];
+
+    // Restoring a snapshot would silently throw away edits made outside bind_manager in between
+    let current = Snapshot::capture(config)?.fingerprint();
+    if undone.last().is_some_and(|last| last.after != current) {
+        return Err(Error::InvalidArgument("The files were changed outside bind_manager since the last operation, refusing to undo.".to_string()));
+    }
+    for pair in undone.windows(2) {
+        if pair[0].after != pair[1].before {
+            return Err(Error::InvalidArgument(format!(
+                "The files were changed outside bind_manager between \"{}\" and \"{}\", refusing to undo past it.",
+                pair[0].command, pair[1].command
+            )));
+        }
+    }
+
+    let first = &undone[0];
+    let path = snapshot_path(config, first.seq);
+    let contents = match read_optional(&Filesystem, &path)? {
+        Some(contents) => contents,
+        None => return Err(Error::InvalidArgument(format!("\"{}\" is too old to undo, its snapshot is gone.", first.command))),
+    };
+    let snapshot: Snapshot = serde_json::from_slice(&contents)?;
+    snapshot.restore(config)?;
+
+    let mut domains: Vec<String> = undone.iter().flat_map(|operation| operation.domains.iter().cloned()).collect();
+    domains.sort();
+    domains.dedup();
+    append_operation(config, &Operation {
+        seq: next_seq(config)?,
+        at: Utc::now(),
+        action: "undo".to_string(),
+        command: format!("undo --steps {}", steps),
+        domains,
+        zones_file: config.zones_file.clone(),
+        before: current,
+        after: snapshot.fingerprint(),
+        undoes: undone.iter().map(|operation| operation.seq).collect(),
+    })?;
+
+    for operation in undone.iter().rev() {
+        println!("Undid: {}", operation.command);
+        logging::event(Level::Info, "undo", None, "undone", Some(&operation.command));
+        // It can't be undone twice, so its snapshot is no use any more
+        let _ = fs::remove_file(snapshot_path(config, operation.seq));
+    }
+
+    reload_bind(config)
+}
+
+fn restore_file(backend: &dyn FileBackend, path: &Path, contents: Option<&[u8]>) -> Result<()> {
+    match contents {
+        Some(contents) => backend.write(path, contents).map_err(Error::writing(path)),
+        None => backend.remove(path).map_err(Error::writing(path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use bind_manager::backend::InMemory;
+
+    const ZONE_A: &str = "zone \"a.example\" {type master; file \"/db\";};\n\n";
+    const ZONE_B: &str = "zone \"b.example\" {type master; file \"/db\";};\n\n";
+
+    // A list kept in memory, with its history in a fresh directory of its own
+    fn config(name: &str) -> Config {
+        let defaults = Config::default();
+        let history_dir = std::env::temp_dir().join(format!("bind_manager-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&history_dir);
+        let backend = Arc::new(InMemory::new().with_file(&defaults.zones_file, ""));
+        Config { no_reload: true, history_dir, backend, ..defaults }
+    }
+
+    // Changes the zones file the way a command would, recording it
+    fn change(config: &Config, command: &str, zones: &str) {
+        let before = Snapshot::capture(config).unwrap();
+        config.backend.write(&config.zones_file, zones.as_bytes()).unwrap();
+        record(config, command.split(' ').next().unwrap(), command, before).unwrap();
+    }
+
+    fn zones(config: &Config) -> String {
+        String::from_utf8(config.backend.read(&config.zones_file).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn undo_steps_back_through_the_operations() {
+        let config = config("undo");
+        change(&config, "add a.example", ZONE_A);
+        change(&config, "add b.example", &format!("{}{}", ZONE_A, ZONE_B));
+        assert_eq!(own_operations(&config).unwrap().len(), 2);
+
+        undo(&config, 1).unwrap();
+        assert_eq!(zones(&config), ZONE_A);
+        // The undone operation drops out, the undo itself is logged but can't be undone in turn
+        let left: Vec<String> = own_operations(&config).unwrap().into_iter().map(|operation| operation.command).collect();
+        assert_eq!(left, vec!["add a.example"]);
+        assert_eq!(load_operations(&config).unwrap().last().map(|operation| operation.undoes.clone()), Some(vec![2]));
+
+        undo(&config, 1).unwrap();
+        assert_eq!(zones(&config), "");
+        assert!(matches!(undo(&config, 1), Err(Error::InvalidArgument(_))));
+        fs::remove_dir_all(&config.history_dir).unwrap();
+    }
+
+    #[test]
+    fn undo_refuses_after_an_edit_outside_bind_manager() {
+        let config = config("edited");
+        change(&config, "add a.example", ZONE_A);
+        let edited = format!("{}// added by hand\n", ZONE_A);
+        config.backend.write(&config.zones_file, edited.as_bytes()).unwrap();
+
+        assert!(matches!(undo(&config, 1), Err(Error::InvalidArgument(_))));
+        assert_eq!(zones(&config), edited);
+        assert_eq!(own_operations(&config).unwrap().len(), 1);
+        fs::remove_dir_all(&config.history_dir).unwrap();
+    }
+
+    #[test]
+    fn undo_puts_back_the_exact_bytes() {
+        let config = config("bytes");
+        // Latin-1 in a comment, which a lossy snapshot would turn into replacement characters
+        let damaged = b"# caf\xe9\r\nzone \"a.example\" {type master; file \"/db\";};\n".to_vec();
+        config.backend.write(&config.zones_file, &damaged).unwrap();
+        change(&config, "add b.example", ZONE_B);
+
+        undo(&config, 1).unwrap();
+        assert_eq!(config.backend.read(&config.zones_file).unwrap(), damaged);
+        fs::remove_dir_all(&config.history_dir).unwrap();
+    }
+
+    #[test]
+    fn text_snapshots_are_still_read() {
+        let snapshot: Snapshot = serde_json::from_str(r#"{"zones": "zone \"a.example\" {};\n", "reason_log": null}"#).unwrap();
+        assert_eq!(snapshot.zones.as_deref(), Some(&b"zone \"a.example\" {};\n"[..]));
+        assert!(snapshot.reason_log.is_none());
+
+        let stored = serde_json::to_string(&snapshot).unwrap();
+        assert!(stored.contains(r#""encoding":"base64""#), "{}", stored);
+        let read_back: Snapshot = serde_json::from_str(&stored).unwrap();
+        assert_eq!(read_back.zones, snapshot.zones);
+    }
+}
@@ -0,0 +1,432 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use chrono::Utc;
+
+use bind_manager::config::Config;
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::state::{ImportProgress, StateFile};
+use bind_manager::{normalize_domain, read_zone_domains, validate_domain, DomainEntry, KnownGood};
+
+use crate::batch::Workspace;
+use crate::{check_blockable_with, confirm, reload_bind, report_count_delta};
+
+#[derive(Clone)]
+pub struct ImportOptions {
+    pub sync: bool,
+    pub prune: bool,
+    pub dry_run: bool,
+    pub strict: bool,
+    // Rewrite the zones file in domain order afterwards, for minimal diffs
+    pub sort: bool,
+    // Write (and record progress) every this many domains instead of once at the end
+    pub batch_size: Option<usize>,
+    pub reload_each_batch: bool,
+    pub pause_secs: Option<u64>,
+    // Continue after the last batch an interrupted import of the same file recorded
+    pub resume: bool,
+    // Use the closest comment above a domain as its reason when the line has none
+    pub comment_reasons: bool,
+    // Domains left out of the import, given directly or in files of one domain per line
+    pub exclude: Vec<String>,
+    pub exclude_file: Option<String>,
+    // Prune without asking. Force imports protected and known-good domains with a warning and
+    // prunes beyond the prune_max_percent safety limit.
+    pub yes: bool,
+    pub force: bool,
+}
+
+struct ImportLine {
+    line: usize,
+    domain: String,
+    reason: Option<String>,
+}
+
+// A line that couldn't be imported: its line number and why
+struct Skipped {
+    line: usize,
+    problem: String,
+}
+
+// Read a line at a time, so a list of millions of domains is never held in memory as text as well
+fn parse_file(path: &str, comment_reasons: bool) -> Result<(Vec<ImportLine>, Vec<Skipped>)> {
+    let file = fs::File::open(path).map_err(Error::reading(Path::new(path)))?;
+    let mut lines = Vec::new();
+    let mut skipped = Vec::new();
+    // Curated lists often head each group of domains with a comment like `# Phishing campaign 2024-06`
+    let mut group_comment: Option<String> = None;
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(Error::reading(Path::new(path)))?;
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('#') {
+            let comment = comment.trim_start_matches('#').trim();
+            if !comment.is_empty() {
+                group_comment = Some(comment.to_string());
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let (domain, reason) = match line.split_once(char::is_whitespace) {
+            Some((domain, reason)) => (domain, Some(reason.trim().to_string())),
+            None if comment_reasons => (line, group_comment.clone()),
+            None => (line, None),
+        };
+        let domain = normalize_domain(domain);
+        match validate_domain(&domain) {
+            Ok(()) => lines.push(ImportLine { line: idx + 1, domain, reason }),
+            Err(err) => skipped.push(Skipped { line: idx + 1, problem: err.to_string() }),
+        }
+    }
+    Ok((lines, skipped))
+}
+
+// Adds every domain in the file. With sync, the file is the desired state: only missing domains
+// are added, and with prune blocked domains missing from the file are removed, all in one reload.
+// Invalid lines are skipped with a warning, or abort the import before anything is written when strict.
+// With a batch size, huge files are written out a batch at a time and can be resumed if interrupted.
+pub fn run(config: &Config, path: &str, options: &ImportOptions) -> Result<()> {
+    let ImportOptions { sync, prune, dry_run, strict, sort, batch_size, reload_each_batch, pause_secs, resume, comment_reasons, yes, force, .. } =
+        *options;
+    let (mut lines, skipped) = parse_file(path, comment_reasons)?;
+    if strict && !skipped.is_empty() {
+        for skip in &skipped {
+            println!("{}:{}: {}", path, skip.line, skip.problem);
+        }
+        return Err(Error::InvalidArgument(format!("{} has invalid lines, nothing was imported.", path)));
+    }
+    for skip in &skipped {
+        println!("Warning! {}:{}: {} Skipping it.", path, skip.line, skip.problem);
+    }
+    let excluded = exclusions(options)?;
+    if !excluded.is_empty() {
+        let mut left_out: HashSet<String> = HashSet::new();
+        lines.retain(|line| {
+            let keep = !excluded.contains(&line.domain);
+            if !keep {
+                left_out.insert(line.domain.clone());
+            }
+            keep
+        });
+        println!("Excluded {} {} from the import.", left_out.len(), if left_out.len() == 1 { "domain" } else { "domains" });
+    }
+    let before = read_zone_domains(config)?.len();
+    let mut workspace = Workspace::load(config)?;
+    if sort {
+        workspace.sort_zones();
+    }
+    if batch_size.is_some() {
+        workspace.append_new_zones();
+    }
+
+    let desired: HashSet<&str> = lines.iter().map(|line| line.domain.as_str()).collect();
+    let resume_after = if resume { resume_point(config, path)? } else { 0 };
+    let mut seen = HashSet::new();
+    let to_add: Vec<&ImportLine> = lines
+        .iter()
+        .filter(|line| seen.insert(line.domain.as_str()))
+        .filter(|line| line.line > resume_after)
+        .filter(|line| !sync || !workspace.domains().contains(&line.domain))
+        .collect();
+    // Checked up front, so a protected domain stops the import before anything is written
+    let known_good = KnownGood::load(config)?;
+    for line in &to_add {
+        let known = known_good.as_ref().and_then(|known_good| known_good.blocked_by(&line.domain));
+        check_blockable_with(config, &line.domain, known, force)
+            .map_err(|err| Error::InvalidArgument(format!("{}:{}: {} Nothing was imported.", path, line.line, err)))?;
+    }
+    let mut to_remove: Vec<String> = if prune {
+        workspace.owned_domains().iter().filter(|domain| !desired.contains(domain.as_str())).cloned().collect()
+    } else {
+        Vec::new()
+    };
+    to_remove.sort();
+
+    if dry_run {
+        let counts = report_dry_run(config, &workspace, &lines, &skipped, &to_remove, sync, resume_after);
+        println!("Dry run of {}, nothing was written:", path);
+        for count in counts {
+            println!("  {}", count);
+        }
+        return Ok(());
+    }
+
+    // A truncated source file would otherwise wipe most of the list
+    if !to_remove.is_empty() {
+        let listed = workspace.domains().len();
+        let percent = to_remove.len() * 100 / listed.max(1);
+        if percent > config.prune_max_percent && !force {
+            return Err(Error::InvalidArgument(format!(
+                "Refusing to prune {} of {} domains ({}%), more than prune_max_percent ({}%). The source file may be incomplete, pass --force if you really mean it.",
+                to_remove.len(),
+                listed,
+                percent,
+                config.prune_max_percent
+            )));
+        }
+        if !confirm::confirm_removal(&to_remove, yes)? {
+            println!("Nothing was imported.");
+            return Ok(());
+        }
+    }
+
+    if resume_after > 0 {
+        println!("Resuming the import of {} after line {}.", path, resume_after);
+    }
+    let size = fs::metadata(path).map_err(Error::reading(Path::new(path)))?.len();
+    let chunk_size = batch_size.unwrap_or(usize::MAX).max(1);
+    let batches = to_add.len().div_ceil(chunk_size);
+    let mut added = 0;
+    let mut reload_pending = false;
+    for (idx, batch) in to_add.chunks(chunk_size).enumerate() {
+        for line in batch {
+            let reason = line.reason.clone().unwrap_or_else(|| config.default_reason.clone());
+            if workspace.add(DomainEntry { reason, ..DomainEntry::new(config, &line.domain) })? {
+                added += 1;
+                logging::event(Level::Info, "import", Some(&line.domain), "added", None);
+            }
+        }
+        if batch_size.is_none() {
+            continue;
+        }
+
+        reload_pending |= workspace.flush()?;
+        let line = batch.last().map_or(resume_after, |line| line.line);
+        StateFile::record_import_progress(config, Some(ImportProgress { path: Path::new(path).to_path_buf(), size, line, at: Utc::now() }));
+        println!("Batch {}/{}: {} domains, up to line {} of {}.", idx + 1, batches, batch.len(), line, path);
+        if reload_each_batch && reload_pending {
+            reload_bind(config)?;
+            reload_pending = false;
+        }
+        if let (Some(pause_secs), true) = (pause_secs, idx + 1 < batches) {
+            thread::sleep(Duration::from_secs(pause_secs));
+        }
+    }
+    for domain in &to_remove {
+        workspace.remove(domain);
+        logging::event(Level::Info, "import", Some(domain), "removed", None);
+    }
+
+    println!("Imported {}: {} added or updated, {} removed.", path, added, to_remove.len());
+    if !skipped.is_empty() {
+        let lines: Vec<String> = skipped.iter().map(|skip| skip.line.to_string()).collect();
+        println!("Skipped {} invalid {}: {}", skipped.len(), if skipped.len() == 1 { "line" } else { "lines" }, lines.join(", "));
+    }
+    let written = workspace.flush()?;
+    report_count_delta(config, before)?;
+    if written || reload_pending {
+        reload_bind(config)?;
+    } else {
+        println!("Already up to date, nothing was written.");
+    }
+    if batch_size.is_some() || resume {
+        StateFile::record_import_progress(config, None);
+    }
+    Ok(())
+}
+
+// Everything an import would do, without writing anything: the new domains, the blocked ones whose
+// reason would change, and the duplicate and invalid lines of the file. Returns the counts.
+fn report_dry_run(
+    config: &Config,
+    workspace: &Workspace,
+    lines: &[ImportLine],
+    skipped: &[Skipped],
+    to_remove: &[String],
+    sync: bool,
+    resume_after: usize,
+) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    let (mut new, mut updated, mut unchanged) = (0, 0, 0);
+    for line in lines {
+        if !seen.insert(line.domain.as_str()) {
+            duplicates.push(line.line);
+            continue;
+        }
+        if line.line <= resume_after {
+            continue;
+        }
+        if !workspace.domains().contains(&line.domain) {
+            println!("+ {}", line.domain);
+            new += 1;
+            continue;
+        }
+        // With sync blocked domains are left as they are
+        let reason = line.reason.as_deref().unwrap_or(&config.default_reason);
+        let current = workspace.entry(&line.domain).map_or(config.default_reason.as_str(), |entry| entry.reason.as_str());
+        if !sync && current != reason {
+            println!("~ {}: {} → {}", line.domain, current, reason);
+            updated += 1;
+        } else {
+            unchanged += 1;
+        }
+    }
+    for domain in to_remove {
+        println!("- {}", domain);
+    }
+
+    let invalid: Vec<usize> = skipped.iter().map(|skip| skip.line).collect();
+    let mut counts = vec![
+        format!("{} new", new),
+        format!("{} already blocked with a different reason, which would be updated", updated),
+        format!("{} already blocked and unchanged", unchanged),
+        format!("{} duplicates within the file{}", duplicates.len(), line_numbers(&duplicates)),
+        format!("{} invalid{}", invalid.len(), line_numbers(&invalid)),
+    ];
+    if !to_remove.is_empty() {
+        counts.push(format!("{} blocked but not in the file, which would be removed", to_remove.len()));
+    }
+    counts
+}
+
+// ` (lines 3, 7 and 12 more)`, empty without any
+fn line_numbers(lines: &[usize]) -> String {
+    const SHOWN: usize = 20;
+    if lines.is_empty() {
+        return String::new();
+    }
+    let shown: Vec<String> = lines.iter().take(SHOWN).map(usize::to_string).collect();
+    let more = if lines.len() > SHOWN { format!(" and {} more", lines.len() - SHOWN) } else { String::new() };
+    format!(" ({} {}{})", if lines.len() == 1 { "line" } else { "lines" }, shown.join(", "), more)
+}
+
+// The normalized domains given with --exclude and listed in the --exclude-file
+fn exclusions(options: &ImportOptions) -> Result<HashSet<String>> {
+    let mut excluded: HashSet<String> = options.exclude.iter().map(|domain| normalize_domain(domain)).collect();
+    if let Some(file) = &options.exclude_file {
+        let contents = fs::read_to_string(file).map_err(Error::reading(Path::new(file)))?;
+        excluded.extend(contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(normalize_domain));
+    }
+    Ok(excluded)
+}
+
+// The last line an interrupted import of this file got through
+fn resume_point(config: &Config, path: &str) -> Result<usize> {
+    let size = fs::metadata(path).map_err(Error::reading(Path::new(path)))?.len();
+    match StateFile::load(config).import_progress {
+        Some(progress) if progress.path == Path::new(path) && progress.size == size => Ok(progress.line),
+        Some(progress) if progress.path == Path::new(path) => {
+            Err(Error::InvalidArgument(format!("{} changed since the interrupted import, it can't be resumed.", path)))
+        }
+        _ => Err(Error::InvalidArgument(format!("There is no interrupted import of {} to resume.", path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use bind_manager::backend::{FileBackend, InMemory};
+    use bind_manager::load_reason_log;
+
+    // Memory that runs out of room after a given number of appends
+    #[derive(Debug)]
+    struct FillingUp {
+        files: InMemory,
+        appends_left: AtomicUsize,
+    }
+
+    impl FileBackend for FillingUp {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.files.read(path)
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.files.write(path, contents)
+        }
+
+        fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            match self.appends_left.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1)) {
+                Ok(_) => self.files.append(path, contents),
+                Err(_) => Err(io::Error::new(io::ErrorKind::StorageFull, "no space left")),
+            }
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            self.files.remove(path)
+        }
+
+        fn canonicalize(&self, path: &Path) -> io::Result<std::path::PathBuf> {
+            self.files.canonicalize(path)
+        }
+    }
+
+    fn options(batch_size: Option<usize>, resume: bool) -> ImportOptions {
+        ImportOptions {
+            sync: false,
+            prune: false,
+            dry_run: false,
+            strict: false,
+            sort: false,
+            batch_size,
+            reload_each_batch: false,
+            pause_secs: None,
+            resume,
+            comment_reasons: false,
+            exclude: Vec::new(),
+            exclude_file: None,
+            yes: false,
+            force: false,
+        }
+    }
+
+    #[test]
+    fn an_interrupted_import_resumes_after_the_last_batch() {
+        let defaults = Config::default();
+        let files = InMemory::new().with_file(&defaults.zones_file, "").with_file(&defaults.zone_db, "");
+        let backend = Arc::new(FillingUp { files, appends_left: AtomicUsize::new(1) });
+        let config = Config { no_reload: true, backend: backend.clone(), ..defaults };
+        let source = std::env::temp_dir().join(format!("bind_manager-import-resume-{}", std::process::id()));
+        fs::write(&source, "a.example\nb.example\nc.example\nd.example\ne.example\n").unwrap();
+        let path = source.to_str().unwrap();
+
+        // The second batch doesn't fit, the first stays written with its progress recorded
+        assert!(matches!(run(&config, path, &options(Some(2), false)), Err(Error::File { writing: true, .. })));
+        assert_eq!(read_zone_domains(&config).unwrap(), vec!["a.example", "b.example"]);
+        assert_eq!(StateFile::load(&config).import_progress.map(|progress| progress.line), Some(2));
+
+        backend.appends_left.store(usize::MAX, Ordering::SeqCst);
+        run(&config, path, &options(Some(2), true)).unwrap();
+        fs::remove_file(&source).unwrap();
+        assert_eq!(read_zone_domains(&config).unwrap(), vec!["a.example", "b.example", "c.example", "d.example", "e.example"]);
+        assert_eq!(load_reason_log(&config).unwrap().len(), 5);
+        assert!(StateFile::load(&config).import_progress.is_none());
+    }
+
+    #[test]
+    fn pruning_most_of_the_list_needs_force() {
+        let defaults = Config::default();
+        let zones: String = ["a", "b", "c", "d", "e"].iter().map(|name| format!("zone \"{}.example\" {{type master; file \"/db\";}};\n\n", name)).collect();
+        let files = InMemory::new().with_file(&defaults.zones_file, zones.clone()).with_file(&defaults.zone_db, "");
+        let config = Config { no_reload: true, backend: Arc::new(files), ..defaults };
+        // As if the source was cut short after its first line
+        let source = std::env::temp_dir().join(format!("bind_manager-import-prune-{}", std::process::id()));
+        fs::write(&source, "a.example\n").unwrap();
+        let path = source.to_str().unwrap();
+        let pruning = |force: bool| ImportOptions { sync: true, prune: true, yes: true, force, ..options(None, false) };
+
+        match run(&config, path, &pruning(false)) {
+            Err(Error::InvalidArgument(message)) => assert!(message.contains("4 of 5 domains (80%)"), "{}", message),
+            other => panic!("expected the prune to be refused, got {:?}", other),
+        }
+        assert_eq!(String::from_utf8(config.backend.read(&config.zones_file).unwrap()).unwrap(), zones);
+
+        run(&config, path, &pruning(true)).unwrap();
+        fs::remove_file(&source).unwrap();
+        assert_eq!(read_zone_domains(&config).unwrap(), vec!["a.example"]);
+    }
+}
@@ -0,0 +1,1230 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// The blacklist logic, usable without the CLI. `BindManager` covers the core operations and
+// returns what happened instead of printing it; the helpers below are shared with the binary.
+
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use std::process::{Child, Output, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+pub mod atomic;
+pub mod backend;
+pub mod config;
+pub mod debug;
+pub mod error;
+pub mod logging;
+pub mod reason_log;
+pub mod state;
+pub mod zone_cache;
+pub mod zones;
+
+use backend::FileBackend;
+use config::{CommentedZones, Config, Mode, ReasonLogFormat, OutputFormat, CONFIG_PATH, DEFAULT_ZONE_TEMPLATE};
+use error::{Error, Result};
+use logging::Level;
+use state::StateFile;
+use zones::{parse_domain_from_line, ZoneSource};
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone)]
+pub struct DomainEntry {
+    pub domain: String,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added_at: Option<DateTime<Utc>>,
+    // When the reason last changed after the domain was added
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_updated_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+    // A link to the report the block is based on, e.g. a threat-intel writeup, kept out of the reason
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_url: Option<String>,
+    // Where the request to block came from, e.g. a ticket or incident number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone_file: Option<String>,
+    #[serde(default, skip_serializing_if = "BlockMode::is_block")]
+    pub mode: BlockMode,
+    // Blocks the whole TLD or suffix (e.g. zip) rather than a single domain; the zone covers every name below it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suffix: bool,
+    // False for a staged block, recorded (e.g. pending approval) but without a zone until it is activated
+    #[serde(default = "active_by_default", skip_serializing_if = "is_active")]
+    pub active: bool,
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+fn is_active(active: &bool) -> bool {
+    *active
+}
+
+// A soft block (log) points the domain at a monitoring zone instead of the sinkhole, to watch its traffic first
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockMode {
+    #[default]
+    Block,
+    Log,
+}
+
+impl BlockMode {
+    pub fn is_block(&self) -> bool {
+        *self == BlockMode::Block
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    pub const NAMES: &'static [&'static str] = &["low", "medium", "high", "critical"];
+
+    pub fn parse(name: &str) -> Option<Severity> {
+        match name.to_ascii_lowercase().as_str() {
+            "low" => Some(Severity::Low),
+            "medium" => Some(Severity::Medium),
+            "high" => Some(Severity::High),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+impl DomainEntry {
+    pub fn new(config: &Config, domain: &str) -> DomainEntry {
+        DomainEntry { domain: domain.to_string(), reason: config.default_reason.clone(), active: true, ..Default::default() }
+    }
+
+    // Why the domain is blocked, as `key=value` pairs for the event logged when it is added
+    pub fn justification(&self) -> String {
+        let mut fields = vec![format!("reason={:?}", self.reason)];
+        let optional = [
+            ("category", self.category.as_deref()),
+            ("source", self.source.as_deref()),
+            ("severity", self.severity.as_ref().map(Severity::as_str)),
+            ("ref", self.reference_url.as_deref()),
+        ];
+        fields.extend(optional.into_iter().filter_map(|(key, value)| Some(format!("{}={:?}", key, value?))));
+        if !self.tags.is_empty() {
+            fields.push(format!("tags={:?}", self.tags.join(",")));
+        }
+        fields.join(" ")
+    }
+}
+
+// Fails if the log can't be decoded, rather than passing it off as empty for the next save to overwrite
+pub fn load_reason_log(config: &Config) -> Result<Vec<DomainEntry>> {
+    let path = config.reason_log.as_path();
+    if !config.backend.exists(path) {
+        return Ok(Vec::new());
+    }
+    let contents = config.backend.read(path).map_err(Error::reading(path))?;
+    let format = reason_log::format_of(config, Some(&contents))?;
+    debug::timed("parsing the reason log", || format.decode(&contents)).map_err(|err| {
+        Error::Config(format!("{} is not a valid {} reason log, fix or restore it before changing anything: {}", path.display(), format.as_str(), err))
+    })
+}
+
+// The JSON Schema of the reason log, an array of entries, for validating it outside bind_manager
+pub fn reason_log_schema() -> schemars::schema::RootSchema {
+    let mut schema = schemars::schema_for!(Vec<DomainEntry>);
+    schema.schema.metadata().title = Some("reason_log.json".to_string());
+    schema
+}
+
+// Returns whether the reason log was written, it is left alone when it already has these entries
+pub fn save_reason_log(config: &Config, entries: &[DomainEntry]) -> Result<bool> {
+    // Keep the format the log is already in unless one is declared
+    let current = config.backend.read(&config.reason_log).ok();
+    save_reason_log_as(config, reason_log::format_of(config, current.as_deref())?, entries)
+}
+
+pub fn save_reason_log_as(config: &Config, format: ReasonLogFormat, entries: &[DomainEntry]) -> Result<bool> {
+    config.check_writable()?;
+    let contents = debug::timed("encoding the reason log", || format.encode(entries))?;
+    if config.backend.read(&config.reason_log).is_ok_and(|current| current == contents) {
+        return Ok(false);
+    }
+    // Parse what was written back before it replaces the log, it has to come out as the same entries
+    let round_trips = |written: &[u8]| match format.decode(written) {
+        Ok(read_back) if format.encode(&read_back).is_ok_and(|again| again == contents) => Ok(()),
+        Ok(_) => Err("the entries read back differ from the ones written".to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    config.backend.write_verified(&config.reason_log, &contents, &round_trips).map_err(Error::writing(&config.reason_log))?;
+    Ok(true)
+}
+
+// The zones file as text. Damaged bytes are replaced rather than failing the read, so a corrupted
+// line doesn't stop the rest of the file from being changed. It is read whole however long its
+// lines are, since writing it back needs all of it.
+pub fn read_zones_file(config: &Config) -> Result<String> {
+    let path = config.zones_file.as_path();
+    let contents = config.backend.read(path).map_err(Error::reading(path))?;
+    Ok(String::from_utf8_lossy(&contents).into_owned())
+}
+
+// Replaces the zones file unless it already has exactly these contents, returning whether it was
+// written. A rewrite that changes nothing would still touch the file and call for a reload.
+pub fn write_zones_file(config: &Config, contents: &[u8]) -> Result<bool> {
+    config.check_writable()?;
+    let path = config.zones_file.as_path();
+    if config.backend.read(path).is_ok_and(|current| current == contents) {
+        return Ok(false);
+    }
+    config.backend.write(path, contents).map_err(Error::writing(path))?;
+    Ok(true)
+}
+
+// Adds zone statements to the end of the zones file, for long runs that would otherwise rewrite
+// the whole file each time they add a few
+pub fn append_zones_file(config: &Config, contents: &[u8]) -> Result<()> {
+    config.check_writable()?;
+    let path = config.zones_file.as_path();
+    config.backend.append(path, contents).map_err(Error::writing(path))
+}
+
+// Domains are case-insensitive and may be typed fully qualified, so compare them in one canonical form
+pub fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+pub fn validate_domain(domain: &str) -> Result<()> {
+    let invalid = |why: &str| Err(Error::InvalidArgument(format!("Invalid domain {}: {}.", domain, why)));
+
+    if domain.is_empty() {
+        return invalid("it is empty");
+    }
+    if domain.len() > 253 {
+        return invalid("it is longer than 253 characters");
+    }
+    for label in domain.split('.') {
+        if label.is_empty() {
+            return invalid("it has an empty label");
+        }
+        if label.len() > 63 {
+            return invalid("a label is longer than 63 characters");
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return invalid("a label starts or ends with a hyphen");
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return invalid("only letters, digits, hyphens and underscores are allowed");
+        }
+    }
+    Ok(())
+}
+
+// Checks that a reference URL is a well-formed http(s) URL with a valid host. Whether it is
+// reachable isn't checked.
+pub fn validate_reference_url(url: &str) -> Result<()> {
+    let invalid = |why: &str| Err(Error::InvalidArgument(format!("Invalid reference URL {}: {}.", url, why)));
+
+    let rest = match url.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") => rest,
+        Some(_) => return invalid("only http and https URLs are accepted"),
+        None => return invalid("it has no http:// or https:// scheme"),
+    };
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return invalid("it contains whitespace");
+    }
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    // An IPv6 address is bracketed, [::1]:8080, since it has colons of its own
+    let (host, port, valid_host) = match authority.strip_prefix('[').and_then(|rest| rest.split_once(']')) {
+        Some((address, port)) => (address, port.strip_prefix(':'), address.parse::<std::net::Ipv6Addr>().is_ok()),
+        None => {
+            let (host, port) = authority.split_once(':').map_or((authority, None), |(host, port)| (host, Some(port)));
+            (host, port, host.parse::<std::net::Ipv4Addr>().is_ok() || validate_domain(&normalize_domain(host)).is_ok())
+        }
+    };
+    if port.is_some_and(|port| port.is_empty() || !port.chars().all(|c| c.is_ascii_digit())) {
+        return invalid("the port is not a number");
+    }
+    if host.is_empty() {
+        return invalid("it has no host");
+    }
+    if !valid_host {
+        return invalid(&format!("{} is not a valid host", host));
+    }
+    Ok(())
+}
+
+// The protected domain that blocking `domain` would take down with it: the domain itself or one
+// below it, since a zone covers every name under it. Subdomains of a protected domain can be blocked.
+pub fn protected_by(config: &Config, domain: &str) -> Option<String> {
+    let domain = normalize_domain(domain);
+    config.protected_domains().into_iter().map(|protected| normalize_domain(&protected)).find(|protected| {
+        *protected == domain || protected.ends_with(&format!(".{}", domain))
+    })
+}
+
+// The domain on the known_good_list that blocking `domain` would block: the domain itself or one
+// below it. None when no list is configured or the check is turned off.
+pub fn known_good_by(config: &Config, domain: &str) -> Result<Option<String>> {
+    let path = match (&config.known_good_list, config.check_known_good) {
+        (Some(path), true) => path,
+        _ => return Ok(None),
+    };
+    let domain = normalize_domain(domain);
+    let below = format!(".{}", domain);
+    let contents = config.backend.read_to_string(path).map_err(Error::reading(path))?;
+    let known_good = known_good_domains(&contents).find(|known| *known == domain || known.ends_with(&below));
+    Ok(known_good)
+}
+
+fn known_good_domains(contents: &str) -> impl Iterator<Item = String> + '_ {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        // Ranked lists (Tranco, Alexa) are CSV with the domain last
+        .map(|line| normalize_domain(line.rsplit(',').next().unwrap_or(line)))
+}
+
+// The known_good_list loaded once, for checking many domains without reading it for each
+pub struct KnownGood {
+    // Each known-good domain and every domain above it, to the known-good domain blocking it takes down
+    covered: HashMap<String, String>,
+}
+
+impl KnownGood {
+    // None when no list is configured or the check is turned off
+    pub fn load(config: &Config) -> Result<Option<KnownGood>> {
+        let path = match (&config.known_good_list, config.check_known_good) {
+            (Some(path), true) => path,
+            _ => return Ok(None),
+        };
+        let contents = config.backend.read_to_string(path).map_err(Error::reading(path))?;
+        let mut covered = HashMap::new();
+        for known in known_good_domains(&contents) {
+            let mut above = known.as_str();
+            while let Some((_, parent)) = above.split_once('.') {
+                covered.entry(parent.to_string()).or_insert_with(|| known.clone());
+                above = parent;
+            }
+            // The domain itself wins over one below it
+            covered.insert(known.clone(), known);
+        }
+        Ok(Some(KnownGood { covered }))
+    }
+
+    // Like known_good_by
+    pub fn blocked_by(&self, domain: &str) -> Option<&str> {
+        self.covered.get(&normalize_domain(domain)).map(String::as_str)
+    }
+}
+
+// Renders the zone statement add_domain writes for a domain, from the configured template
+pub fn render_zone(config: &Config, domain: &str, zone_file: Option<&str>) -> Result<String> {
+    let file = zone_file.unwrap_or(&config.zone_db);
+    let template = config.zone_template();
+    if template.contains("{forwarders}") && config.forwarders.is_empty() {
+        let message = match config.mode {
+            Mode::Allowlist => "Allowlist mode needs the forwarders allowed domains are resolved through",
+            Mode::Blocklist => "Forward zones need the sinkhole forwarders blocked domains are sent to",
+        };
+        return Err(Error::Config(format!("{}, set them in {}.", message, CONFIG_PATH)));
+    }
+
+    let forwarders: String = config.forwarders.iter().map(|forwarder| format!("{};", forwarder)).collect::<Vec<_>>().join(" ");
+    let rendered = template.replace("{domain}", domain).replace("{file}", file).replace("{forwarders}", &forwarders);
+
+    // The rest of the tool reads zones back line by line, so the template has to stay parseable
+    if rendered.contains('\n') || parse_domain_from_line(&rendered).as_deref() != Some(domain) {
+        return Err(Error::Config(format!(
+            "The zone_template in {} must be a single-line zone \"{{domain}}\" statement.",
+            CONFIG_PATH
+        )));
+    }
+    Ok(rendered)
+}
+
+// With output both, regenerates the hosts file from the zones so it lists exactly the blocked
+// domains. Returns whether it changed.
+pub fn write_hosts_file(config: &Config) -> Result<bool> {
+    if config.output != OutputFormat::Both {
+        return Ok(false);
+    }
+
+    let mut contents = format!("# Generated by bind_manager from {}, do not edit.\n", config.zones_file.display());
+    for domain in read_zone_domains(config)? {
+        contents.push_str(&format!("0.0.0.0 {}\n", normalize_domain(&domain)));
+    }
+    let path = config.hosts_file.as_path();
+    if config.backend.read_to_string(path).is_ok_and(|current| current == contents) {
+        return Ok(false);
+    }
+    config.backend.write(path, contents.as_bytes()).map_err(Error::writing(path))?;
+    Ok(true)
+}
+
+// The zone that sinkholes everything in allowlist mode, so only the forwarded zones resolve
+pub fn catch_all_zone(config: &Config) -> String {
+    DEFAULT_ZONE_TEMPLATE.replace("{domain}", ".").replace("{file}", &config.zone_db)
+}
+
+// Zone names from the zones file and its includes, leaving out the allowlist catch-all
+pub fn read_zone_domains(config: &Config) -> Result<Vec<String>> {
+    Ok(zone_domains_in(config, &zones::read_sources(config.backend.as_ref(), &config.zones_file)?))
+}
+
+fn zone_domains_in(config: &Config, sources: &[ZoneSource]) -> Vec<String> {
+    let mut domains = debug::timed("parsing the zones", || zones::domains_in(sources));
+    if config.mode == Mode::Allowlist {
+        domains.retain(|domain| domain != ".");
+    }
+    domains
+}
+
+// The zone db file an entry's zone statement points at, None meaning the configured zone_db
+pub fn entry_zone_file<'a>(config: &'a Config, entry: &'a DomainEntry) -> Result<Option<&'a str>> {
+    match (entry.mode, &config.log_zone_db) {
+        (BlockMode::Block, _) => Ok(entry.zone_file.as_deref()),
+        (BlockMode::Log, _) if config.mode == Mode::Allowlist => {
+            Err(Error::InvalidArgument("Soft blocks are not supported in allowlist mode.".to_string()))
+        }
+        (BlockMode::Log, _) if config.forwards_listed() => {
+            Err(Error::InvalidArgument("Soft blocks need master zones, they are not supported with zone_type forward.".to_string()))
+        }
+        (BlockMode::Log, Some(log_zone_db)) => Ok(Some(log_zone_db)),
+        (BlockMode::Log, None) => Err(Error::InvalidArgument(format!("Soft blocks need log_zone_db set in {}.", CONFIG_PATH))),
+    }
+}
+
+// Applies an add to an existing entry, returning whether anything actually changed
+pub fn merge_entry(entry: &mut DomainEntry, new_entry: DomainEntry) -> bool {
+    let new_tags: Vec<String> = new_entry.tags.into_iter().filter(|tag| !entry.tags.contains(tag)).collect();
+    let note_changed = !new_entry.note.is_empty() && new_entry.note != entry.note;
+    let reference_changed = new_entry.reference_url.is_some() && new_entry.reference_url != entry.reference_url;
+    let source_changed = new_entry.source.is_some() && new_entry.source != entry.source;
+    let severity_changed = new_entry.severity.is_some() && new_entry.severity != entry.severity;
+
+    if entry.reason == new_entry.reason
+        && entry.category == new_entry.category
+        && new_tags.is_empty()
+        && !note_changed
+        && !reference_changed
+        && !source_changed
+        && !severity_changed
+        && entry.suffix == new_entry.suffix
+        && entry.active == new_entry.active
+    {
+        return false;
+    }
+
+    // Update the reason for the existing domain
+    if entry.reason != new_entry.reason {
+        entry.reason_updated_at = Some(Utc::now());
+    }
+    entry.reason = new_entry.reason;
+    entry.category = new_entry.category;
+    entry.tags.extend(new_tags);
+    entry.suffix = new_entry.suffix;
+    entry.active = new_entry.active;
+    if note_changed {
+        entry.note = new_entry.note;
+    }
+    if reference_changed {
+        entry.reference_url = new_entry.reference_url;
+    }
+    if source_changed {
+        entry.source = new_entry.source;
+    }
+    if severity_changed {
+        entry.severity = new_entry.severity;
+    }
+    true
+}
+
+// In allowlist mode, puts the catch-all zone at the top of the zones file if it isn't there yet.
+// Returns whether it had to be added.
+pub fn ensure_catch_all(config: &Config, contents: &mut Vec<u8>) -> bool {
+    if config.mode != Mode::Allowlist {
+        return false;
+    }
+
+    let has_catch_all = String::from_utf8_lossy(contents).lines().any(|line| parse_domain_from_line(line).as_deref() == Some("."));
+    if !has_catch_all {
+        contents.splice(0..0, format!("{}\n\n", catch_all_zone(config)).into_bytes());
+    }
+    !has_catch_all
+}
+
+// Puts the configured header comment at the very top of the zones file if it isn't there yet.
+// Lines of the header that aren't comments are turned into ones, so it is never read as a zone.
+// Returns whether it had to be added.
+pub fn ensure_header(config: &Config, contents: &mut Vec<u8>) -> bool {
+    let Some(header) = config.header.as_deref().filter(|header| !header.trim().is_empty()) else {
+        return false;
+    };
+
+    let header: Vec<String> = header
+        .lines()
+        .map(|line| if zones::is_comment_or_blank(line) { line.to_string() } else { format!("# {}", line) })
+        .collect();
+    let text = String::from_utf8_lossy(contents).into_owned();
+    if text.lines().take(header.len()).eq(header.iter().map(String::as_str)) {
+        return false;
+    }
+    contents.splice(0..0, format!("{}\n\n", header.join("\n")).into_bytes());
+    true
+}
+
+pub fn check_zone_db(config: &Config, path: &str) -> Option<String> {
+    match config.backend.read(Path::new(path)) {
+        Ok(_) => None,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Some(format!("Zone db file {} does not exist, BIND will fail to load the zones.", path)),
+        Err(err) => Some(format!("Zone db file {} is not readable ({}), BIND will fail to load the zones.", path, err)),
+    }
+}
+
+// Removes every (normalized) domain in the set with a single pass over each file, returning the ones that were present.
+// Both files are updated or neither: the new contents of both are worked out before anything is written.
+pub fn remove_domain_set(config: &Config, domains: &HashSet<String>) -> Result<HashSet<String>> {
+    config.check_writable()?;
+    let mut removed = HashSet::new();
+
+    let mut entries = load_reason_log(config)?;
+    let entry_count = entries.len();
+    entries.retain(|entry| {
+        let domain = normalize_domain(&entry.domain);
+        let keep = !domains.contains(&domain);
+        if !keep {
+            removed.insert(domain);
+        }
+        keep
+    });
+    let entries_changed = entries.len() < entry_count;
+
+    let contents = read_zones_file(config)?;
+    let mut filtered = String::with_capacity(contents.len());
+    let mut zone_removed = false;
+    for line in contents.lines() {
+        match parse_domain_from_line(line) {
+            Some(domain) if domains.contains(&normalize_domain(&domain)) => {
+                removed.insert(normalize_domain(&domain));
+                zone_removed = true;
+            }
+            _ => {
+                filtered.push_str(line);
+                filtered.push('\n');
+            }
+        }
+    }
+    let mut filtered = filtered.into_bytes();
+    ensure_header(config, &mut filtered);
+
+    save_reason_log_and_zones(config, entries_changed.then_some(&entries), zone_removed.then_some(&filtered))?;
+    Ok(removed)
+}
+
+// Saves the reason log and then the zones file, either of them skipped when None. If the zones file
+// can't be written the reason log is put back as it was, so the two files never disagree.
+pub fn save_reason_log_and_zones(config: &Config, entries: Option<&[DomainEntry]>, zones: Option<&[u8]>) -> Result<()> {
+    let reason_log_before = config.backend.read(&config.reason_log).ok();
+    let reason_log_saved = match entries {
+        Some(entries) => save_reason_log(config, entries)?,
+        None => false,
+    };
+    let Some(zones) = zones else {
+        return Ok(());
+    };
+    if let Err(err) = write_zones_file(config, zones) {
+        if reason_log_saved {
+            let rollback = match &reason_log_before {
+                Some(before) => config.backend.write(&config.reason_log, before),
+                None => config.backend.remove(&config.reason_log),
+            };
+            if let Err(rollback) = rollback {
+                return Err(Error::Io(io::Error::new(
+                    rollback.kind(),
+                    format!("{} Putting {} back failed too, the two files disagree now: {}", err, config.reason_log.display(), rollback),
+                )));
+            }
+        }
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadOutcome {
+    Reloaded,
+    // The reload command isn't installed, so the change is on disk but BIND hasn't been told
+    CommandNotFound { program: String },
+    // Reloading is turned off with no_reload
+    Disabled,
+    // Left for later by the debounce window, `elapsed` seconds after the previous reload
+    Pending { elapsed: i64 },
+}
+
+// Reloads BIND unless the previous reload was within the configured debounce window. The
+// generated hosts file is brought up to date first either way.
+pub fn reload(config: &Config) -> Result<ReloadOutcome> {
+    write_hosts_file(config)?;
+    if config.no_reload {
+        return Ok(ReloadOutcome::Disabled);
+    }
+
+    // Coalesce reloads that arrive in quick succession, BIND picks the change up with the next one.
+    // Not with strict_reload, which needs to know now whether BIND accepted the change.
+    if let Some(elapsed) = debounced(config, &StateFile::load(config), Utc::now()) {
+        StateFile::mark_reload_pending(config);
+        logging::event(Level::Info, "reload", None, "pending", None);
+        return Ok(ReloadOutcome::Pending { elapsed });
+    }
+
+    reload_now(config)
+}
+
+// How many seconds ago this list was last reloaded, if that is within the debounce window
+fn debounced(config: &Config, state: &StateFile, now: DateTime<Utc>) -> Option<i64> {
+    if config.reload_debounce_secs == 0 || config.strict_reload {
+        return None;
+    }
+    let window = Duration::seconds(config.reload_debounce_secs as i64);
+    let last = state.reloads(&config.reload_key()).last_reload.filter(|last| last.success)?;
+    let elapsed = now.signed_duration_since(last.at);
+    (elapsed >= Duration::zero() && elapsed < window).then(|| elapsed.num_seconds())
+}
+
+pub fn reload_now(config: &Config) -> Result<ReloadOutcome> {
+    let mut words = config.reload_command.split_whitespace();
+    let program = match words.next() {
+        Some(program) => program,
+        None => return Err(Error::Config(format!("The reload_command in {} is empty.", CONFIG_PATH))),
+    };
+
+    // A missing binary only means BIND wasn't told, the change itself is already on disk
+    debug::log(format_args!("running the reload command: {}", config.reload_command));
+    let child = std::process::Command::new(program).args(words).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+    let waited = debug::timed("reloading", || child.and_then(|child| wait_with_timeout(child, config.reload_timeout_secs)));
+    if let Ok(Some(output)) = &waited {
+        debug::log(format_args!("{} exited with {}", program, output.status));
+    }
+    let output = match waited {
+        Ok(Some(output)) => output,
+        Ok(None) => {
+            let message = format!("{} did not finish within {}s", program, config.reload_timeout_secs);
+            StateFile::record_reload(config, false, Some(message.clone()));
+            logging::event(Level::Error, "reload", None, "timed out", Some(&message));
+            return Err(Error::Reload(format!(
+                "Timed out reloading BIND, the change was written but may not be live yet. {} was killed after {}s (reload_timeout_secs).",
+                program, config.reload_timeout_secs
+            )));
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound && config.strict_reload => {
+            logging::event(Level::Error, "reload", None, "failed", Some(&format!("{} not found", program)));
+            return Err(Error::Reload(format!("{} not found on PATH, BIND could not be reloaded.", program)));
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            logging::event(Level::Warning, "reload", None, "skipped", Some(&format!("{} not found", program)));
+            return Ok(ReloadOutcome::CommandNotFound { program: program.to_string() });
+        }
+        Err(err) => return Err(Error::Reload(format!("Failed to run {}, the change was written but is not live yet: {}", program, err))),
+    };
+
+    if output.status.success() {
+        logging::event(Level::Info, "reload", None, "succeeded", None);
+        StateFile::record_reload(config, true, None);
+        Ok(ReloadOutcome::Reloaded)
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        StateFile::record_reload(config, false, Some(stderr.clone()).filter(|message| !message.is_empty()));
+        logging::event(Level::Error, "reload", None, "failed", Some(&stderr));
+
+        // The files on disk were already updated, only BIND has yet to pick them up
+        let mut message = "Failed to reload BIND, the change was written but is not live yet.".to_string();
+        if !stderr.is_empty() {
+            message = format!("{} {} said: {}", message, program, stderr);
+        }
+        Err(Error::Reload(message))
+    }
+}
+
+// Waits for the child to exit, killing it and returning None if it runs longer than timeout_secs (0 waits forever)
+fn wait_with_timeout(mut child: Child, timeout_secs: u64) -> io::Result<Option<Output>> {
+    if timeout_secs > 0 {
+        let deadline = Instant::now() + std::time::Duration::from_secs(timeout_secs);
+        while child.try_wait()?.is_none() {
+            if Instant::now() >= deadline {
+                child.kill()?;
+                child.wait()?;
+                return Ok(None);
+            }
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+    }
+    child.wait_with_output().map(Some)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddOutcome {
+    Added,
+    Updated,
+    Unchanged,
+    // Not added because the zone is commented out in the zones file, with commented_zones present
+    Commented,
+    // Recorded without a zone, to be activated later
+    Staged,
+}
+
+#[derive(Debug)]
+pub struct AddReport {
+    pub domain: String,
+    pub outcome: AddOutcome,
+    // Set in allowlist mode when the catch-all zone had to be written first
+    pub catch_all_added: bool,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct Listing {
+    // Every zone sorted by domain, with a default entry for zones that have no reason recorded
+    pub entries: Vec<DomainEntry>,
+    // Reason log entries whose zone is gone, so they aren't blocked
+    pub orphaned: Vec<DomainEntry>,
+    // Staged entries, recorded but not blocked until they are activated
+    pub staged: Vec<DomainEntry>,
+    // Zones whose reason log entry spells the domain differently (case, trailing dot): the zone's
+    // spelling and the reason log's
+    pub respelled: Vec<(String, String)>,
+    // Damaged lines of the zones files that were skipped
+    pub warnings: Vec<String>,
+}
+
+// The core operations on one configured list. Changes are only written to disk, call `reload`
+// once they're done so BIND picks them up.
+pub struct BindManager {
+    config: Config,
+}
+
+impl BindManager {
+    pub fn new(config: Config) -> BindManager {
+        BindManager { config }
+    }
+
+    // Works on the files of `backend` instead of the ones configured, e.g. `InMemory` ones for tests
+    pub fn with_backend(config: Config, backend: Arc<dyn FileBackend>) -> BindManager {
+        BindManager { config: Config { backend, ..config } }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn add(&self, new_entry: DomainEntry) -> Result<AddReport> {
+        let config = &self.config;
+        config.check_writable()?;
+        let domain = normalize_domain(&new_entry.domain);
+        validate_domain(&domain)?;
+        let mut entries = load_reason_log(config)?;
+        let mut report = AddReport { domain: domain.clone(), outcome: AddOutcome::Added, catch_all_added: false, warnings: Vec::new() };
+        // Logged with the event, so the audit trail has the whole justification from the time of the block
+        let justification = new_entry.justification();
+
+        let existing = entries.iter().position(|entry| normalize_domain(&entry.domain) == domain);
+        let activating = existing.is_some_and(|index| !entries[index].active) && new_entry.active;
+        if existing.is_some_and(|index| entries[index].active) && !new_entry.active {
+            return Err(Error::InvalidArgument(format!("Domain {} is already blocked, it can't be staged.", domain)));
+        }
+
+        // Check if the domain already exists
+        if let Some(entry) = existing.filter(|_| !activating).map(|index| &mut entries[index]) {
+            // Nothing to write if the reason is unchanged
+            if !merge_entry(entry, new_entry) {
+                logging::event(Level::Info, "add", Some(&domain), "unchanged", None);
+                report.outcome = AddOutcome::Unchanged;
+                return Ok(report);
+            }
+
+            logging::event(Level::Info, "add", Some(&domain), "updated", Some(&entry.justification()));
+            // Still staged, so there is no zone to reload
+            report.outcome = if entry.active { AddOutcome::Updated } else { AddOutcome::Staged };
+        } else if !new_entry.active {
+            // Staged: only the reason log entry, the zone is written when it is activated
+            entries.push(DomainEntry { domain: domain.clone(), added_at: Some(Utc::now()), ..new_entry });
+            logging::event(Level::Info, "add", Some(&domain), "staged", Some(&justification));
+            report.outcome = AddOutcome::Staged;
+        } else {
+            let zones_path = config.zones_file.as_path();
+            let mut contents = config.backend.read(zones_path).map_err(Error::reading(zones_path))?;
+            if config.commented_zones == CommentedZones::Present
+                && zones::commented_domains(&String::from_utf8_lossy(&contents)).iter().any(|zone| normalize_domain(zone) == domain)
+            {
+                logging::event(Level::Info, "add", Some(&domain), "commented out", None);
+                report.outcome = AddOutcome::Commented;
+                return Ok(report);
+            }
+
+            // BIND refuses to load the zones if the db file a zone points at is missing
+            let zone_file = entry_zone_file(config, &new_entry)?;
+            report.warnings.extend(check_zone_db(config, zone_file.unwrap_or(&config.zone_db)));
+
+            // Append the domain to the zones file
+            let entry_format = format!("{}\n\n", render_zone(config, &domain, zone_file)?);
+
+            // Add the new domain entry, or activate the staged one
+            match existing {
+                Some(index) => {
+                    merge_entry(&mut entries[index], new_entry);
+                }
+                None => entries.push(DomainEntry { domain: domain.clone(), added_at: Some(Utc::now()), ..new_entry }),
+            }
+
+            // Rewrite rather than append in place, so a concurrent list never sees a half-written line
+            report.catch_all_added = ensure_catch_all(config, &mut contents);
+            ensure_header(config, &mut contents);
+            // A hand edit may have left the last line unterminated, which the new zone would be glued onto
+            if contents.last().is_some_and(|&byte| byte != b'\n') {
+                contents.push(b'\n');
+            }
+            contents.extend_from_slice(entry_format.as_bytes());
+            write_zones_file(config, &contents)?;
+
+            logging::event(Level::Info, "add", Some(&domain), "added", Some(&justification));
+        }
+
+        // Save the updated entries back to the reason_log.json file
+        save_reason_log(config, &entries)?;
+        Ok(report)
+    }
+
+    // Writes the zone of a staged domain, so the block takes effect
+    pub fn activate(&self, domain: &str) -> Result<AddReport> {
+        let domain = normalize_domain(domain);
+        let entry = load_reason_log(&self.config)?.into_iter().find(|entry| normalize_domain(&entry.domain) == domain);
+        match entry {
+            Some(entry) if !entry.active => self.add(DomainEntry { active: true, ..entry }),
+            Some(_) => Err(Error::InvalidArgument(format!("Domain {} is already active.", domain))),
+            None => Err(Error::NotFound(format!("Domain {} is not staged.", domain))),
+        }
+    }
+
+    // Fails with NotFound if the domain has neither a zone nor a reason log entry
+    pub fn remove(&self, domain: &str) -> Result<()> {
+        let domain = normalize_domain(domain);
+        let removed = remove_domain_set(&self.config, &HashSet::from([domain.clone()]))?;
+        if removed.is_empty() {
+            return Err(Error::NotFound(format!("Domain {} not found.", domain)));
+        }
+
+        logging::event(Level::Info, "del", Some(&domain), "removed", None);
+        Ok(())
+    }
+
+    pub fn list(&self, category: Option<&str>) -> Result<Listing> {
+        let config = &self.config;
+        let in_category = |entry: &DomainEntry| {
+            category.is_none_or(|category| entry.category.as_deref().is_some_and(|c| c.eq_ignore_ascii_case(category)))
+        };
+
+        // Read the zones file, and any files it includes, alongside the reasons
+        let mut entries = load_reason_log(config)?;
+        let sources = zones::read_sources(config.backend.as_ref(), &config.zones_file)?;
+        let warnings = sources
+            .iter()
+            .flat_map(|source| {
+                let path = source.path.display();
+                zones::junk_lines(&source.contents).into_iter().map(move |(line, problem)| format!("{}:{}: {}, skipped it.", path, line, problem))
+            })
+            .collect();
+        let mut listed_domains = zone_domains_in(config, &sources);
+        listed_domains.sort();
+
+        let zone_set: HashSet<String> = listed_domains.iter().map(|domain| normalize_domain(domain)).collect();
+        let mut orphaned: Vec<DomainEntry> = Vec::new();
+        let mut staged: Vec<DomainEntry> = Vec::new();
+        entries.retain(|entry| {
+            let listed = zone_set.contains(&normalize_domain(&entry.domain));
+            match (listed, entry.active) {
+                (false, true) if in_category(entry) => orphaned.push(entry.clone()),
+                (false, false) if in_category(entry) => staged.push(entry.clone()),
+                _ => {}
+            }
+            listed
+        });
+        orphaned.sort_by(|a, b| a.domain.cmp(&b.domain));
+        staged.sort_by(|a, b| a.domain.cmp(&b.domain));
+
+        // Matched up normalized, so `Example.com.` in the zones file still gets the reason of `example.com`
+        let entries_map: HashMap<String, &DomainEntry> = entries.iter().map(|entry| (normalize_domain(&entry.domain), entry)).collect();
+        let mut respelled = Vec::new();
+        let listed = listed_domains
+            .iter()
+            .map(|domain| match entries_map.get(&normalize_domain(domain)) {
+                Some(entry) if entry.domain != *domain => {
+                    respelled.push((domain.clone(), entry.domain.clone()));
+                    DomainEntry { domain: domain.clone(), ..(*entry).clone() }
+                }
+                Some(entry) => (*entry).clone(),
+                None => DomainEntry::new(config, domain),
+            })
+            .filter(|entry| in_category(entry))
+            .collect();
+        Ok(Listing { entries: listed, orphaned, staged, respelled, warnings })
+    }
+
+    // The entry for a blocked domain, with its domain normalized, or None if it isn't blocked. A
+    // domain under a suffix block gets the suffix block's entry. Uses the zone_cache when one is configured.
+    pub fn check(&self, domain: &str) -> Result<Option<DomainEntry>> {
+        self.check_in(domain, zone_cache::zone_set(&self.config)?)
+    }
+
+    // Looks the domain up in the zones files themselves, bypassing the zone_cache
+    pub fn check_uncached(&self, domain: &str) -> Result<Option<DomainEntry>> {
+        let zones: HashSet<String> = read_zone_domains(&self.config)?.iter().map(|zone| normalize_domain(zone)).collect();
+        self.check_in(domain, zones)
+    }
+
+    fn check_in(&self, domain: &str, zones: HashSet<String>) -> Result<Option<DomainEntry>> {
+        let domain = normalize_domain(domain);
+        let entries = load_reason_log(&self.config)?;
+
+        // Entries come back spelled normalized, so the domain tells an exact match from a covering suffix block
+        let normalized = |entry: DomainEntry| DomainEntry { domain: normalize_domain(&entry.domain), ..entry };
+        if zones.contains(&domain) {
+            let entry = entries.into_iter().find(|entry| normalize_domain(&entry.domain) == domain);
+            return Ok(Some(entry.map(normalized).unwrap_or_else(|| DomainEntry::new(&self.config, &domain))));
+        }
+
+        let covering = entries.into_iter().filter(|entry| entry.suffix).find(|entry| {
+            let suffix = normalize_domain(&entry.domain);
+            zones.contains(&suffix) && domain.ends_with(&format!(".{}", suffix))
+        });
+        Ok(covering.map(normalized))
+    }
+
+    pub fn reload(&self) -> Result<ReloadOutcome> {
+        reload(&self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::InMemory;
+
+    // A list kept in memory, with the zone db present so adds don't warn about it
+    fn manager(zones: &str) -> (BindManager, Arc<InMemory>) {
+        let config = Config::default();
+        let backend = Arc::new(InMemory::new().with_file(&config.zones_file, zones).with_file(&config.zone_db, ""));
+        (BindManager::with_backend(config, backend.clone()), backend)
+    }
+
+    fn entry(domain: &str, reason: &str) -> DomainEntry {
+        DomainEntry { domain: domain.to_string(), reason: reason.to_string(), active: true, ..Default::default() }
+    }
+
+    fn zones(manager: &BindManager, backend: &InMemory) -> String {
+        backend.contents(&manager.config().zones_file).unwrap_or_default()
+    }
+
+    #[test]
+    fn add_writes_the_zone_and_the_reason() {
+        let (manager, backend) = manager("");
+        let report = manager.add(entry("example.com", "phishing")).unwrap();
+        assert!(matches!(report.outcome, AddOutcome::Added));
+        assert!(report.warnings.is_empty());
+        assert_eq!(read_zone_domains(manager.config()).unwrap(), vec!["example.com"]);
+        assert!(zones(&manager, &backend).ends_with("\n\n"));
+
+        let entries = load_reason_log(manager.config()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].domain, "example.com");
+        assert_eq!(entries[0].reason, "phishing");
+        assert!(entries[0].added_at.is_some());
+    }
+
+    #[test]
+    fn add_again_only_updates_the_reason() {
+        let (manager, backend) = manager("");
+        manager.add(entry("example.com", "phishing")).unwrap();
+        let written = zones(&manager, &backend);
+
+        assert!(matches!(manager.add(entry("example.com", "phishing")).unwrap().outcome, AddOutcome::Unchanged));
+        assert!(matches!(manager.add(entry("Example.com.", "malware")).unwrap().outcome, AddOutcome::Updated));
+        assert_eq!(zones(&manager, &backend), written);
+        assert_eq!(load_reason_log(manager.config()).unwrap()[0].reason, "malware");
+    }
+
+    #[test]
+    fn add_refuses_invalid_and_read_only() {
+        let (manager, _) = manager("");
+        assert!(matches!(manager.add(entry("not a domain", "x")), Err(Error::InvalidArgument(_))));
+
+        let read_only = BindManager::with_backend(Config { read_only: true, ..Config::default() }, Arc::new(InMemory::new()));
+        assert!(matches!(read_only.add(entry("example.com", "x")), Err(Error::ReadOnly(_))));
+    }
+
+    #[test]
+    fn add_after_a_last_line_without_a_newline() {
+        let last = "zone \"old.example\" {type master; file \"/db\";};";
+        let (manager, backend) = manager(last);
+        manager.add(entry("example.com", "phishing")).unwrap();
+
+        let zones = zones(&manager, &backend);
+        assert!(zones.starts_with(&format!("{}\n", last)));
+        assert_eq!(read_zone_domains(manager.config()).unwrap(), vec!["old.example", "example.com"]);
+    }
+
+    #[test]
+    fn commented_zones_absent_adds_a_fresh_zone() {
+        let commented = "# zone \"example.com\" {type master; file \"/db\";};\n";
+        let (manager, backend) = manager(commented);
+        assert_eq!(manager.config().commented_zones, CommentedZones::Absent);
+
+        assert!(matches!(manager.add(entry("example.com", "phishing")).unwrap().outcome, AddOutcome::Added));
+        assert!(zones(&manager, &backend).starts_with(commented));
+        assert_eq!(read_zone_domains(manager.config()).unwrap(), vec!["example.com"]);
+    }
+
+    #[test]
+    fn commented_zones_present_leaves_it_commented_out() {
+        let commented = "/* zone \"Example.com.\" {type master; file \"/db\";}; */\n";
+        let config = Config { commented_zones: CommentedZones::Present, ..Config::default() };
+        let backend = Arc::new(InMemory::new().with_file(&config.zones_file, commented));
+        let manager = BindManager::with_backend(config, backend.clone());
+
+        assert!(matches!(manager.add(entry("example.com", "phishing")).unwrap().outcome, AddOutcome::Commented));
+        assert_eq!(zones(&manager, &backend), commented);
+        assert!(read_zone_domains(manager.config()).unwrap().is_empty());
+        assert!(load_reason_log(manager.config()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn remove_drops_the_zone_and_the_reason() {
+        let (manager, backend) = manager("");
+        manager.add(entry("example.com", "phishing")).unwrap();
+        manager.add(entry("example.org", "malware")).unwrap();
+
+        manager.remove("example.com").unwrap();
+        assert_eq!(read_zone_domains(manager.config()).unwrap(), vec!["example.org"]);
+        assert!(!zones(&manager, &backend).contains("example.com"));
+        let entries = load_reason_log(manager.config()).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.domain.as_str()).collect::<Vec<_>>(), vec!["example.org"]);
+
+        assert!(matches!(manager.remove("example.com"), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn remove_ignores_case_and_a_trailing_dot() {
+        let (manager, _) = manager("zone \"Mixed.Example.\" {type master; file \"/db\";};\n");
+        save_reason_log(manager.config(), &[entry("Mixed.Example.", "typed by hand")]).unwrap();
+        manager.add(entry("example.com", "phishing")).unwrap();
+
+        manager.remove("EXAMPLE.com.").unwrap();
+        manager.remove("mixed.example").unwrap();
+        assert!(read_zone_domains(manager.config()).unwrap().is_empty());
+        assert!(load_reason_log(manager.config()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn remove_works_past_damaged_bytes() {
+        let (manager, backend) = manager("");
+        manager.add(entry("example.com", "phishing")).unwrap();
+        let mut zones = backend.read(&manager.config().zones_file).unwrap();
+        zones.extend_from_slice(b"\xff\xfe garbage\n");
+        backend.write(&manager.config().zones_file, &zones).unwrap();
+
+        manager.remove("example.com").unwrap();
+        assert!(read_zone_domains(manager.config()).unwrap().is_empty());
+        assert!(load_reason_log(manager.config()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn list_sorts_zones_and_fills_in_missing_reasons() {
+        let (manager, _) = manager("zone \"b.example\" {type master; file \"/db\";};\nzone \"a.example\" {type master; file \"/db\";};\n");
+        save_reason_log(manager.config(), &[entry("b.example", "tracking")]).unwrap();
+        manager.add(DomainEntry { category: Some("ads".to_string()), ..entry("c.example", "ads") }).unwrap();
+
+        let listing = manager.list(None).unwrap();
+        let listed: Vec<(&str, &str)> = listing.entries.iter().map(|entry| (entry.domain.as_str(), entry.reason.as_str())).collect();
+        assert_eq!(listed, vec![("a.example", config::DEFAULT_REASON), ("b.example", "tracking"), ("c.example", "ads")]);
+        assert!(listing.orphaned.is_empty() && listing.staged.is_empty() && listing.warnings.is_empty());
+
+        let ads = manager.list(Some("ADS")).unwrap();
+        assert_eq!(ads.entries.len(), 1);
+        assert_eq!(ads.entries[0].domain, "c.example");
+    }
+
+    #[test]
+    fn list_matches_reasons_spelled_differently() {
+        let (manager, _) = manager("zone \"Example.COM.\" {type master; file \"/db\";};\nzone \"other.example\" {type master; file \"/db\";};\n");
+        save_reason_log(manager.config(), &[entry("example.com", "phishing"), entry("OTHER.example.", "malware")]).unwrap();
+
+        let listing = manager.list(None).unwrap();
+        let listed: Vec<(&str, &str)> = listing.entries.iter().map(|entry| (entry.domain.as_str(), entry.reason.as_str())).collect();
+        assert_eq!(listed, vec![("Example.COM.", "phishing"), ("other.example", "malware")]);
+        assert!(listing.orphaned.is_empty());
+        assert_eq!(
+            listing.respelled,
+            vec![("Example.COM.".to_string(), "example.com".to_string()), ("other.example".to_string(), "OTHER.example.".to_string())]
+        );
+    }
+
+    #[test]
+    fn list_reports_orphaned_and_staged_entries() {
+        let (manager, _) = manager("");
+        manager.add(DomainEntry { active: false, ..entry("staged.example", "pending") }).unwrap();
+        let orphan = entry("gone.example", "old");
+        save_reason_log(manager.config(), &[orphan, load_reason_log(manager.config()).unwrap().remove(0)]).unwrap();
+
+        let listing = manager.list(None).unwrap();
+        assert!(listing.entries.is_empty());
+        assert_eq!(listing.orphaned[0].domain, "gone.example");
+        assert_eq!(listing.staged[0].domain, "staged.example");
+    }
+
+    #[test]
+    fn check_finds_blocked_domains_and_suffix_blocks() {
+        let (manager, _) = manager("");
+        manager.add(entry("example.com", "phishing")).unwrap();
+        manager.add(DomainEntry { suffix: true, ..entry("zip", "abused tld") }).unwrap();
+
+        assert_eq!(manager.check("EXAMPLE.com.").unwrap().map(|entry| entry.reason), Some("phishing".to_string()));
+        // A zone only covers the names below it when it is a suffix block
+        assert!(manager.check("www.example.com").unwrap().is_none());
+        assert_eq!(manager.check("files.zip").unwrap().map(|entry| entry.domain), Some("zip".to_string()));
+        assert!(manager.check("example.org").unwrap().is_none());
+        assert_eq!(manager.check_uncached("example.com").unwrap().map(|entry| entry.reason), Some("phishing".to_string()));
+    }
+
+    #[test]
+    fn check_returns_the_entry_normalized() {
+        let (manager, _) = manager("zone \"Example.COM.\" {type master; file \"/db\";};\nzone \"ZIP\" {type master; file \"/db\";};\n");
+        save_reason_log(manager.config(), &[entry("Example.COM.", "phishing"), DomainEntry { suffix: true, ..entry("ZIP", "abused tld") }]).unwrap();
+
+        let exact = manager.check("example.com").unwrap().unwrap();
+        assert_eq!((exact.domain.as_str(), exact.reason.as_str()), ("example.com", "phishing"));
+        assert_eq!(manager.check("zip").unwrap().map(|entry| entry.domain), Some("zip".to_string()));
+        assert_eq!(manager.check("files.zip").unwrap().map(|entry| entry.domain), Some("zip".to_string()));
+    }
+
+    // Memory whose zones file can be read but not written
+    #[derive(Debug)]
+    struct ReadOnlyZones {
+        files: InMemory,
+        zones_file: std::path::PathBuf,
+    }
+
+    impl FileBackend for ReadOnlyZones {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.files.read(path)
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            match path == self.zones_file {
+                true => Err(io::Error::new(io::ErrorKind::StorageFull, "no space left")),
+                false => self.files.write(path, contents),
+            }
+        }
+
+        fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            match path == self.zones_file {
+                true => Err(io::Error::new(io::ErrorKind::StorageFull, "no space left")),
+                false => self.files.append(path, contents),
+            }
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            self.files.remove(path)
+        }
+
+        fn canonicalize(&self, path: &Path) -> io::Result<std::path::PathBuf> {
+            self.files.canonicalize(path)
+        }
+    }
+
+    #[test]
+    fn remove_puts_the_reason_log_back_when_the_zones_file_fails() {
+        let (manager, memory) = manager("");
+        manager.add(entry("example.com", "phishing")).unwrap();
+        manager.add(entry("example.org", "malware")).unwrap();
+        let config = manager.config();
+        // Written by hand so the rollback has to restore these exact bytes, not a re-encoding
+        let reason_log = b"[ {\"domain\": \"example.com\", \"reason\": \"phishing\"},\n  {\"domain\": \"example.org\", \"reason\": \"malware\"} ]\n".to_vec();
+        let zones = memory.read(&config.zones_file).unwrap();
+
+        let files = InMemory::new().with_file(&config.zones_file, zones.clone()).with_file(&config.reason_log, reason_log.clone());
+        let failing = Arc::new(ReadOnlyZones { files, zones_file: config.zones_file.clone() });
+        let manager = BindManager::with_backend(Config::default(), failing.clone());
+
+        assert!(matches!(manager.remove("example.com"), Err(Error::File { writing: true, .. })));
+        assert_eq!(failing.read(&manager.config().reason_log).unwrap(), reason_log);
+        assert_eq!(failing.read(&manager.config().zones_file).unwrap(), zones);
+    }
+
+    #[test]
+    fn a_reason_log_in_another_format_is_refused_not_overwritten() {
+        let config = Config { reason_log_format: Some(ReasonLogFormat::Toml), ..Config::default() };
+        let json = b"[{\"domain\": \"example.com\", \"reason\": \"phishing\"}]".to_vec();
+        let backend = Arc::new(InMemory::new().with_file(&config.zones_file, "").with_file(&config.zone_db, "").with_file(&config.reason_log, json.clone()));
+        let manager = BindManager::with_backend(config, backend.clone());
+
+        assert!(matches!(load_reason_log(manager.config()), Err(Error::Config(_))));
+        assert!(manager.add(entry("example.org", "malware")).is_err());
+        assert_eq!(backend.read(&manager.config().reason_log).unwrap(), json);
+    }
+
+    #[test]
+    fn an_undecodable_reason_log_is_an_error() {
+        let (manager, backend) = manager("");
+        let path = manager.config().reason_log.clone();
+        backend.write(&path, b"[{\"domain\": ").unwrap();
+        assert!(matches!(load_reason_log(manager.config()), Err(Error::Config(_))));
+    }
+
+    #[test]
+    fn profiles_are_debounced_separately() {
+        let profile = |name: &str| Config { profile: Some(name.to_string()), zones_file: format!("/etc/bind/{}.zones", name).into(), reload_debounce_secs: 60, ..Config::default() };
+        let (guest, staff) = (profile("guest"), profile("staff"));
+        let now = Utc::now();
+        let mut state = StateFile::default();
+        let reloaded = state::ReloadRecord { at: now - Duration::seconds(5), success: true, message: None };
+        state.reloads.entry(guest.reload_key()).or_default().last_reload = Some(reloaded);
+
+        assert_eq!(debounced(&guest, &state, now), Some(5));
+        assert_eq!(debounced(&staff, &state, now), None);
+        // A --zones-file override is a list of its own too
+        let other = Config { zones_file: "/tmp/other.zones".into(), ..profile("guest") };
+        assert_eq!(debounced(&other, &state, now), None);
+        assert_eq!(debounced(&guest, &state, now + Duration::seconds(60)), None);
+    }
+}
@@ -0,0 +1,94 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Structured operational events, written as one JSON object per line.
+// Nothing is emitted unless a log file or syslog is enabled in the config.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Debug,
+    #[default]
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub file: Option<PathBuf>,
+    pub syslog: bool,
+    // Events below this level are dropped
+    pub level: Level,
+}
+
+#[derive(Serialize)]
+struct Event<'a> {
+    timestamp: DateTime<Utc>,
+    level: Level,
+    action: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    domain: Option<&'a str>,
+    result: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+}
+
+static LOGGER: OnceLock<LoggingConfig> = OnceLock::new();
+
+pub fn init(config: &LoggingConfig) {
+    let _ = LOGGER.set(config.clone());
+}
+
+pub fn event(level: Level, action: &str, domain: Option<&str>, result: &str, message: Option<&str>) {
+    let config = match LOGGER.get() {
+        Some(config) if level >= config.level && (config.file.is_some() || config.syslog) => config,
+        _ => return,
+    };
+
+    let event = Event { timestamp: Utc::now(), level, action, domain, result, message };
+    let line = match serde_json::to_string(&event) {
+        Ok(line) => line,
+        Err(_) => return,
+    };
+
+    // Logging must never get in the way of the operation itself, so failures here are ignored
+    if let Some(path) = &config.file {
+        if let Ok(mut file) = OpenOptions::new().append(true).create(true).open(path) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+    if config.syslog {
+        send_syslog(level, &line);
+    }
+}
+
+#[cfg(unix)]
+fn send_syslog(level: Level, line: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    // Facility "user" (1) combined with the matching syslog severity
+    let severity = match level {
+        Level::Debug => 7,
+        Level::Info => 6,
+        Level::Warning => 4,
+        Level::Error => 3,
+    };
+    let message = format!("<{}>{}[{}]: {}", 8 + severity, env!("CARGO_PKG_NAME"), std::process::id(), line);
+
+    if let Ok(socket) = UnixDatagram::unbound() {
+        let _ = socket.send_to(message.as_bytes(), "/dev/log");
+    }
+}
+
+#[cfg(not(unix))]
+fn send_syslog(_level: Level, _line: &str) {}
@@ -3,202 +3,2241 @@
 * Copyright (c) 2024 TheFinnaCompany Ltd
 */
 
-use std::collections::HashMap;
-use std::fs::{self, OpenOptions};
-use std::io::{self, BufRead, BufReader, Write};
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::process::ExitCode;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, Utc};
 use structopt::StructOpt;
-use serde::{Deserialize, Serialize};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+// First, so its macros are defined for the other modules
+#[macro_use]
+mod output;
+mod batch;
+mod bulk_edit;
+mod bundle;
+mod confirm;
+mod export;
+mod history;
+mod import;
+mod prune;
+mod stale;
+mod summary;
+mod sync;
+
+use bind_manager::config::{Config, Mode, OutputFormat, ReasonLogFormat, ZoneType, CONFIG_PATH};
+use bind_manager::debug;
+use bind_manager::error::{self, Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::state::{ListingRecord, StateFile};
+use bind_manager::zones::{self, is_comment_or_blank, parse_domain_from_line, parse_include, parse_zone_statement};
+use bind_manager::{
+    check_zone_db, ensure_header, entry_zone_file, known_good_by, load_reason_log, normalize_domain, protected_by, read_zone_domains, read_zones_file, remove_domain_set,
+    render_zone, save_reason_log, save_reason_log_as, validate_domain, validate_reference_url, write_zones_file, AddOutcome, AddReport, BindManager, BlockMode, DomainEntry, Listing, ReloadOutcome, Severity,
+};
 
 #[derive(StructOpt)]
 #[structopt(name = "bind_manager", about = "A CLI tool to manage BIND blacklisted zones.")]
-enum Cli {
+struct Cli {
+    #[structopt(long, global = true, help = "Use a profile from the config file.")]
+    profile: Option<String>,
+    #[structopt(long, global = true, help = "Refuse any change, only inspect the list.")]
+    read_only: bool,
+    #[structopt(long, global = true, help = "Write changes but do not reload BIND.")]
+    no_reload: bool,
+    #[structopt(long, global = true, conflicts_with = "no-reload", help = "Reload BIND afterwards even if nothing changed, skipping the debounce.")]
+    force_reload: bool,
+    #[structopt(long, global = true, conflicts_with = "no-reload", help = "Roll the change back and fail if BIND does not accept it.")]
+    strict_reload: bool,
+    #[structopt(long, global = true, help = "Print timings of each phase and the reload command run to stderr.")]
+    debug: bool,
+    #[structopt(long, global = true, help = "End with a machine-parseable `CHANGED added=N updated=N removed=N reloaded=yes|no` line.")]
+    summary: bool,
+    #[structopt(long, global = true, parse(from_os_str), help = "Use this zones file instead of the configured one.")]
+    zones_file: Option<PathBuf>,
+    #[structopt(long, global = true, parse(from_os_str), help = "Use this reason log instead of the configured one.")]
+    reason_log: Option<PathBuf>,
+    #[structopt(long, global = true, parse(from_os_str), help = "Answer read commands from a snapshot written by export --snapshot, changing nothing.")]
+    from_snapshot: Option<PathBuf>,
+    #[structopt(long, global = true, parse(from_os_str), help = "Write the result (listings, stats, JSON) to this file, messages go to stderr.")]
+    output: Option<PathBuf>,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
     Add {
         #[structopt(help = "The domain to be added.")]
         domain: String,
-        #[structopt(help = "The reason for blacklisting.", default_value = "No reason provided.")]
+        #[structopt(help = "The reason for blacklisting. Asked for on a terminal when left out, otherwise the configured default_reason.")]
+        reason: Option<String>,
+        #[structopt(long, parse(from_os_str), conflicts_with = "reason", help = "Read the reason from a file instead.")]
+        reason_file: Option<PathBuf>,
+        #[structopt(long, help = "The reason category, one of the categories allowed in the config.")]
+        category: Option<String>,
+        #[structopt(long, help = "Accept a category that is not in the config, or block a protected or known-good domain.")]
+        force: bool,
+        #[structopt(long = "tag", number_of_values = 1, help = "A tag to attach to the domain, may be repeated.")]
+        tags: Vec<String>,
+        #[structopt(long, help = "A longer free-form note, shown by show and list --json.")]
+        note: Option<String>,
+        #[structopt(long, help = "Where the request to block came from, e.g. a ticket or incident number.")]
+        source: Option<String>,
+        #[structopt(long, possible_values = Severity::NAMES, help = "How severe the threat is.")]
+        severity: Option<String>,
+        #[structopt(long = "ref", help = "A http(s) link to the report the block is based on, e.g. a threat-intel writeup.")]
+        reference_url: Option<String>,
+        #[structopt(long, help = "Point this domain at a different zone db file than the configured one.")]
+        zone_file: Option<String>,
+        #[structopt(long, help = "Reject URLs and host:port input instead of extracting the hostname.")]
+        strict: bool,
+        #[structopt(long, possible_values = &["block", "log"], conflicts_with = "zone-file", help = "Sinkhole the domain (block) or only point it at the configured log_zone_db (log).")]
+        mode: Option<String>,
+        #[structopt(long, help = "Block a whole TLD or suffix, e.g. .zip, and every domain under it.")]
+        suffix: bool,
+        #[structopt(long, requires = "suffix", help = "Do not ask for confirmation before blocking a widely used suffix.")]
+        yes: bool,
+        #[structopt(long, help = "Only record the domain and its reason, it is blocked once activated.")]
+        staged: bool,
+    },
+    #[structopt(about = "Block a domain with this reason unless it already is, for idempotent provisioning.")]
+    Ensure {
+        #[structopt(help = "The domain that has to be blocked.")]
+        domain: String,
+        #[structopt(help = "The reason it has to be blocked with.")]
         reason: String,
     },
+    Activate {
+        #[structopt(help = "The staged domain to block.")]
+        domain: String,
+    },
+    Promote {
+        #[structopt(help = "The soft-blocked domain to block for real.")]
+        domain: String,
+    },
+    Preview {
+        #[structopt(help = "The domain to render.")]
+        domain: String,
+        #[structopt(long, help = "Render with a different zone db file than the configured one.")]
+        zone_file: Option<String>,
+    },
+    Edit {
+        #[structopt(help = "The domain to be edited.")]
+        domain: String,
+        #[structopt(long, help = "The new reason for blacklisting.")]
+        reason: Option<String>,
+        #[structopt(long, help = "The new note, an empty string clears it.")]
+        note: Option<String>,
+    },
+    #[structopt(about = "Edit every reason at once in $EDITOR.")]
+    EditAll,
+    Relabel {
+        #[structopt(help = "The reason to replace, matched ignoring case and whitespace.")]
+        from: String,
+        #[structopt(help = "The reason to use instead.")]
+        to: String,
+    },
+    Show {
+        #[structopt(help = "The domain to show.")]
+        domain: String,
+        #[structopt(long, help = "Print the entry as JSON.")]
+        json: bool,
+    },
+    Tag {
+        #[structopt(help = "A domain pattern, where * matches any run of characters and ? a single one.")]
+        pattern: String,
+        #[structopt(long, number_of_values = 1, help = "A tag to add to every matching domain, may be repeated.")]
+        add: Vec<String>,
+        #[structopt(long, number_of_values = 1, help = "A tag to remove from every matching domain, may be repeated.")]
+        remove: Vec<String>,
+    },
     Del {
         #[structopt(help = "The domain to be removed.")]
         domain: String,
     },
-    List,
-    About
+    #[structopt(about = "Remove the domain shown with this number by the last list --index.")]
+    DelIndex {
+        #[structopt(help = "The number list --index showed next to the domain.")]
+        n: usize,
+    },
+    Check {
+        #[structopt(help = "The domain to look up.")]
+        domain: String,
+        #[structopt(long, help = "Print the answer as a single line of JSON.")]
+        json: bool,
+        #[structopt(long, help = "Parse the zones files instead of using the configured zone_cache.")]
+        no_cache: bool,
+    },
+    DelMatch {
+        #[structopt(help = "A shell-style pattern, * matches any run of characters and ? any single one.")]
+        pattern: String,
+        #[structopt(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+    },
+    DelDomainTree {
+        #[structopt(help = "The registrable domain (or any name under it) to remove with every blocked subdomain.")]
+        domain: String,
+        #[structopt(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+        #[structopt(long, help = "Only show what would be removed.")]
+        dry_run: bool,
+    },
+    RemoveFile {
+        #[structopt(help = "A file with one domain to be removed per line.")]
+        file: String,
+        #[structopt(long, help = "Do not ask for confirmation.")]
+        yes: bool,
+    },
+    Import {
+        #[structopt(help = "A file with one domain per line, optionally followed by its reason.")]
+        file: String,
+        #[structopt(long, help = "Treat the file as the desired state and only add what is missing.")]
+        sync: bool,
+        #[structopt(long, requires = "sync", help = "With --sync, also remove blocked domains that are not in the file.")]
+        prune: bool,
+        #[structopt(long, help = "Only show what would change.")]
+        dry_run: bool,
+        #[structopt(long, help = "Abort the whole import on any invalid line instead of skipping it.")]
+        strict: bool,
+        #[structopt(long, help = "Rewrite the zones file sorted by domain afterwards.")]
+        sort: bool,
+        #[structopt(long, help = "Write the changes every this many domains and record the progress, for huge files.")]
+        batch_size: Option<usize>,
+        #[structopt(long, requires = "batch-size", help = "Reload BIND after every batch rather than once at the end.")]
+        reload_each_batch: bool,
+        #[structopt(long, requires = "batch-size", help = "Wait this many seconds between batches.")]
+        pause_secs: Option<u64>,
+        #[structopt(long, conflicts_with = "dry-run", help = "Continue an interrupted batched import of the same file.")]
+        resume: bool,
+        #[structopt(long, help = "Use the nearest # comment above a domain as its reason when the line gives none.")]
+        comment_reasons: bool,
+        #[structopt(long, number_of_values = 1, help = "Leave this domain out of the import, can be given several times.")]
+        exclude: Vec<String>,
+        #[structopt(long, help = "Leave the domains listed in this file, one per line, out of the import.")]
+        exclude_file: Option<String>,
+        #[structopt(long, requires = "prune", help = "Do not ask for confirmation before pruning.")]
+        yes: bool,
+        #[structopt(long, help = "Import protected or known-good domains with a warning, and prune even more than prune_max_percent of the list.")]
+        force: bool,
+    },
+    Sync {
+        #[structopt(long, help = "Also normalize the zone names in the zones file.")]
+        rewrite: bool,
+    },
+    #[structopt(about = "Rewrite the reason log in another format.")]
+    ConvertReasonLog {
+        #[structopt(possible_values = &["json", "jsonl", "toml"], help = "The format to convert the reason log to.")]
+        format: String,
+    },
+    Batch {
+        #[structopt(help = "A file of add/del commands, one per line. Reads stdin when omitted.")]
+        file: Option<String>,
+        #[structopt(long, help = "Add protected or known-good domains anyway, with a warning.")]
+        force: bool,
+    },
+    List {
+        #[structopt(long, help = "Only list domains in this reason category.")]
+        category: Option<String>,
+        #[structopt(long, help = "Do not truncate long reasons to fit the terminal.")]
+        wide: bool,
+        #[structopt(long, conflicts_with = "wide", help = "Truncate reasons to at most this many characters.")]
+        max_reason_width: Option<usize>,
+        #[structopt(long, help = "Print the entries as JSON.")]
+        json: bool,
+        #[structopt(long, conflicts_with = "json", help = "Print stable tab-separated domain, reason and added_at lines for scripts.")]
+        porcelain: bool,
+        #[structopt(long, help = "Also show the zone db file each domain points at.")]
+        show_target: bool,
+        #[structopt(long, conflicts_with_all = &["json", "porcelain", "template"], help = "Number the domains, for removing them with del-index.")]
+        index: bool,
+        #[structopt(
+            long,
+            conflicts_with_all = &["json", "porcelain"],
+            help = "Print each entry with this template, e.g. \"| {domain} | {reason} |\". Placeholders: {domain}, {reason}, {category}, {tags}, {note}, {source}, {severity}, {ref}, {added_at}, {target}; \\t and \\n for tabs and newlines, {{ and }} for braces."
+        )]
+        template: Option<String>,
+    },
+    Backup {
+        #[structopt(long, parse(from_os_str), help = "Where to write the bundle of the zones file and reason log.")]
+        bundle: PathBuf,
+    },
+    Export {
+        #[structopt(long, default_value = "domains", possible_values = export::FORMATS, help = "One domain per line, hosts file lines or zone statements.")]
+        format: String,
+        #[structopt(long, possible_values = &["tld"], requires = "output-dir", help = "Write one file per TLD instead of everything to stdout.")]
+        split_by: Option<String>,
+        #[structopt(long, parse(from_os_str), requires = "split-by", help = "The directory the per-TLD files are written to.")]
+        output_dir: Option<PathBuf>,
+        #[structopt(long, parse(from_os_str), conflicts_with = "split-by", help = "Write a versioned snapshot of the whole list for --from-snapshot replicas instead.")]
+        snapshot: Option<PathBuf>,
+    },
+    Restore {
+        #[structopt(long, parse(from_os_str), help = "A bundle written by backup.")]
+        bundle: PathBuf,
+    },
+    Stale {
+        #[structopt(long, parse(from_os_str), help = "A BIND query log to look for queries in.")]
+        query_log: PathBuf,
+        #[structopt(long, help = "Only count queries on or after this date (YYYY-MM-DD).")]
+        since: String,
+        #[structopt(long, help = "Remove the stale domains instead of only listing them.")]
+        prune: bool,
+        #[structopt(long, requires = "prune", help = "Do not ask for confirmation before pruning.")]
+        yes: bool,
+    },
+    #[structopt(about = "Show, and with --yes remove, the blocks added longer ago than an age.")]
+    Prune {
+        #[structopt(long, help = "Prune blocks added longer ago than this, e.g. 90d, 12w, 6m or 1y.")]
+        older_than: String,
+        #[structopt(long, help = "Only prune domains with this tag.")]
+        tag: Option<String>,
+        #[structopt(long, help = "Only prune domains whose reason contains this text, ignoring case.")]
+        reason: Option<String>,
+        #[structopt(long, help = "Only prune domains in this reason category.")]
+        category: Option<String>,
+        #[structopt(long, help = "Remove them, otherwise they are only listed.")]
+        yes: bool,
+    },
+    Doctor {
+        #[structopt(long, help = "Re-encode or remove domains that fail validation.")]
+        fix: bool,
+        #[structopt(long, requires = "fix", help = "Do not ask for confirmation before fixing.")]
+        yes: bool,
+        #[structopt(long, help = "Also flag reasons shared by suspiciously many unrelated domains.")]
+        shared_reasons: bool,
+    },
+    Selftest,
+    #[structopt(about = "Quick pass/fail check for liveness probes: exits 0 and prints OK when healthy.")]
+    Healthcheck,
+    Stats {
+        #[structopt(long, help = "Show how many domains were added each day.")]
+        by_day: bool,
+        #[structopt(long, possible_values = &["day", "week", "month"], help = "Show how many domains were added per day, week or month.")]
+        bucket: Option<String>,
+        #[structopt(long, help = "Print the stats, or the timeline, as JSON.")]
+        json: bool,
+    },
+    Status {
+        #[structopt(long, help = "Print the summary as JSON.")]
+        json: bool,
+    },
+    Reload,
+    About {
+        #[structopt(long, help = "Print the name, version and authors as JSON.")]
+        json: bool,
+    },
+    #[structopt(setting = structopt::clap::AppSettings::Hidden, about = "Print the JSON Schema of the reason log.")]
+    Schema,
+    History {
+        #[structopt(long, default_value = "20", help = "Show at most this many operations.")]
+        limit: usize,
+    },
+    Audit {
+        #[structopt(long, help = "Only show operations on or after this date (YYYY-MM-DD) or time (RFC 3339).")]
+        since: Option<String>,
+        #[structopt(long, help = "Only show operations on or before this date (YYYY-MM-DD) or time (RFC 3339).")]
+        until: Option<String>,
+        #[structopt(long, help = "Only show operations that changed this domain.")]
+        domain: Option<String>,
+        #[structopt(long, help = "Only show this kind of operation, e.g. add, del or edit.")]
+        action: Option<String>,
+        #[structopt(long, help = "Show the oldest operations first.")]
+        oldest_first: bool,
+        #[structopt(long, help = "Print the operations as JSON.")]
+        json: bool,
+    },
+    Undo {
+        #[structopt(long, default_value = "1", help = "Revert this many of the most recent operations.")]
+        steps: usize,
+    },
 }
 
-const ZONES_FILE_PATH: &str = "/etc/bind/blacklisted.zones";
-const REASON_LOG_PATH: &str = "/etc/bind/reason_log.json";
-const BLACKLIST_ZONE: &str = "/etc/bind/zones/master/blockeddomains.db";
+impl Command {
+    // Whether the command changes the list or BIND, which read-only mode refuses up front
+    fn writes(&self) -> bool {
+        match self {
+            Command::Add { .. }
+            | Command::Ensure { .. }
+            | Command::Activate { .. }
+            | Command::Promote { .. }
+            | Command::Edit { .. }
+            | Command::EditAll
+            | Command::Relabel { .. }
+            | Command::Tag { .. }
+            | Command::Del { .. }
+            | Command::DelIndex { .. }
+            | Command::DelMatch { .. }
+            | Command::RemoveFile { .. }
+            | Command::Sync { .. }
+            | Command::ConvertReasonLog { .. }
+            | Command::Batch { .. }
+            | Command::Restore { .. }
+            | Command::Undo { .. }
+            | Command::Reload => true,
+            Command::Import { dry_run, .. } | Command::DelDomainTree { dry_run, .. } => !dry_run,
+            Command::Stale { prune, .. } => *prune,
+            Command::Prune { yes, .. } => *yes,
+            Command::Doctor { fix, .. } => *fix,
+            Command::Preview { .. }
+            | Command::Show { .. }
+            | Command::Backup { .. }
+            | Command::Export { .. }
+            | Command::Check { .. }
+            | Command::List { .. }
+            | Command::Selftest
+            | Command::Healthcheck
+            | Command::Stats { .. }
+            | Command::Status { .. }
+            | Command::About { .. }
+            | Command::Schema
+            | Command::History { .. }
+            | Command::Audit { .. } => false,
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // Usage errors exit with 2 rather than clap's default of 1, help and version still exit with 0
+    let matches = match Cli::clap().get_matches_from_safe(std::env::args_os()) {
+        Ok(matches) => matches,
+        Err(err) if matches!(err.kind, structopt::clap::ErrorKind::HelpDisplayed | structopt::clap::ErrorKind::VersionDisplayed) => err.exit(),
+        Err(err) => {
+            eprintln!("{}", err.message);
+            return ExitCode::from(error::EXIT_USAGE);
+        }
+    };
+
+    // The subcommand name is what the audit log calls the action
+    let action = matches.subcommand_name().unwrap_or_default().to_string();
+    match run(Cli::from_clap(&matches), &action) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            logging::event(Level::Error, "command", None, "failed", Some(&err.to_string()));
+            match &err {
+                Error::NotFound(message) => eprintln!("{}", message),
+                other => eprintln!("Error: {}", other),
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug)]
-struct DomainEntry {
-    domain: String,
-    reason: String,
+fn run(args: Cli, action: &str) -> Result<()> {
+    if args.debug {
+        debug::enable();
+    }
+    debug::timed(format_args!("{}", action), || run_command(args, action))
 }
 
-fn main() -> io::Result<()> {
-    let args = Cli::from_args();
+fn run_command(args: Cli, action: &str) -> Result<()> {
+    let mut config = Config::load(args.profile.as_deref())?;
+    config.read_only |= args.read_only;
+    config.no_reload |= args.no_reload;
+    config.strict_reload |= args.strict_reload;
+    // Flags win over the config file and the profile, for one-off runs against another copy
+    if let Some(zones_file) = args.zones_file {
+        config.zones_file = zones_file;
+    }
+    if let Some(reason_log) = args.reason_log {
+        config.reason_log = reason_log;
+    }
+    if let Some(snapshot) = &args.from_snapshot {
+        export::load_snapshot(&mut config, snapshot)?;
+    }
+    let manager = BindManager::new(config);
+    let config = manager.config();
+    logging::init(&config.logging);
+
+    if args.command.writes() || args.force_reload {
+        config.check_writable()?;
+    }
+
+    // Every change lands in the audit log with a snapshot of the files before it, except undo which records itself
+    let before = match args.command {
+        Command::Undo { .. } => None,
+        ref command if command.writes() => Some(history::Snapshot::capture(config)?),
+        _ => None,
+    };
+    if let Some(path) = &args.output {
+        output::to_file(path)?;
+    }
+    let mut result = dispatch(&manager, args.command);
+    let written = output::finish();
+    // Unless the command already reloaded, e.g. to flush a pending reload or test that BIND responds
+    if args.force_reload && result.is_ok() && !summary::was_reloaded() {
+        result = reload_now(config);
+    }
+    // With strict_reload a change BIND didn't accept is undone, so the files match what is live
+    let before = match (&result, before) {
+        (Err(Error::Reload(message)), Some(before)) if config.strict_reload => {
+            match before.restore(config).and_then(|()| bind_manager::write_hosts_file(config)) {
+                Ok(_) => {
+                    logging::event(Level::Warning, "reload", None, "rolled back", Some(message));
+                    result = Err(Error::Reload(format!("{}\nWith strict_reload the change was rolled back.", message)));
+                    None
+                }
+                // The reload failure is what went wrong first, so it stays the error, with the rollback's on top
+                Err(err) => {
+                    logging::event(Level::Error, "reload", None, "rollback failed", Some(&err.to_string()));
+                    result = Err(Error::Reload(format!("{}\nWith strict_reload the change should have been rolled back, but that failed too: {}", message, err)));
+                    // Still recorded, so it can be undone once whatever stopped the rollback is fixed
+                    Some(before)
+                }
+            }
+        }
+        (_, before) => before,
+    };
+    if args.summary {
+        summary::print();
+    }
+    if let Some(before) = before {
+        let command_line: Vec<String> = std::env::args().skip(1).collect();
+        // The change itself went through, so a history that can't be written only costs the ability to undo it
+        if let Err(err) = history::record(config, action, &command_line.join(" "), before) {
+            println!("Warning! Could not record the change in {}: {}", config.history_dir.display(), err);
+        }
+    }
+    result.and(written)
+}
 
-    match args {
-        Cli::Add { domain, reason } => add_domain(&domain, &reason)?,
-        Cli::Del { domain } => remove_domain(&domain)?,
-        Cli::List => list_domains()?,
-        Cli::About => about(),
+fn dispatch(manager: &BindManager, command: Command) -> Result<()> {
+    let config = manager.config();
+    match command {
+        Command::Add { domain, reason, reason_file, category, force, tags, note, source, severity, reference_url, zone_file, strict, mode, suffix, yes, staged } => {
+            let domain = match suffix {
+                true => match suffix_from_input(&domain, yes)? {
+                    Some(suffix) => suffix,
+                    None => {
+                        println!("Nothing was added.");
+                        return Ok(());
+                    }
+                },
+                false => hostname_from_input(&domain, strict)?,
+            };
+            check_blockable(config, &domain, force)?;
+            let reason = match reason_file {
+                Some(path) => read_reason_file(&path)?,
+                None => match reason {
+                    Some(reason) => reason,
+                    None => confirm::ask_reason(&normalize_domain(&domain))?.unwrap_or_else(|| config.default_reason.clone()),
+                },
+            };
+            let category = match category {
+                Some(category) => Some(resolve_category(config, &category, force)?),
+                None => None,
+            };
+            if let Some(url) = &reference_url {
+                validate_reference_url(url)?;
+            }
+            let mode = if mode.as_deref() == Some("log") { BlockMode::Log } else { BlockMode::Block };
+            let entry = DomainEntry {
+                reason,
+                category,
+                tags,
+                note: note.unwrap_or_default(),
+                reference_url,
+                source: source.map(|source| source.trim().to_string()).filter(|source| !source.is_empty()),
+                severity: severity.as_deref().and_then(Severity::parse),
+                zone_file,
+                mode,
+                suffix,
+                active: !staged,
+                ..DomainEntry::new(config, &domain)
+            };
+            add_domain(manager, entry)?
+        }
+        Command::Ensure { domain, reason } => ensure_domain(config, &domain, reason)?,
+        Command::Activate { domain } => activate_domain(manager, &domain)?,
+        Command::Promote { domain } => promote_domain(config, &domain)?,
+        Command::Preview { domain, zone_file } => preview(config, &domain, zone_file.as_deref())?,
+        Command::Edit { domain, reason, note } => edit_domain(config, &domain, reason, note)?,
+        Command::EditAll => bulk_edit::run(config)?,
+        Command::Relabel { from, to } => relabel(config, &from, &to)?,
+        Command::Show { domain, json } => show_domain(config, &domain, json)?,
+        Command::Tag { pattern, add, remove } => tag_domains(config, &pattern, &add, &remove)?,
+        Command::Del { domain } => remove_domain(manager, &domain)?,
+        Command::DelIndex { n } => remove_by_index(manager, n)?,
+        Command::DelMatch { pattern, yes } => remove_matching(config, &pattern, yes)?,
+        Command::DelDomainTree { domain, yes, dry_run } => remove_domain_tree(config, &domain, yes, dry_run)?,
+        Command::RemoveFile { file, yes } => remove_file(config, &file, yes)?,
+        Command::Import {
+            file,
+            sync,
+            prune,
+            dry_run,
+            strict,
+            sort,
+            batch_size,
+            reload_each_batch,
+            pause_secs,
+            resume,
+            comment_reasons,
+            exclude,
+            exclude_file,
+            yes,
+            force,
+        } => {
+            let options = import::ImportOptions {
+                sync,
+                prune,
+                dry_run,
+                strict,
+                sort,
+                batch_size,
+                reload_each_batch,
+                pause_secs,
+                resume,
+                comment_reasons,
+                exclude,
+                exclude_file,
+                yes,
+                force,
+            };
+            import::run(config, &file, &options)?
+        }
+        Command::Sync { rewrite } => sync::run(config, rewrite)?,
+        Command::ConvertReasonLog { format } => convert_reason_log(config, &format)?,
+        Command::Batch { file, force } => batch::run(config, file.as_deref(), force)?,
+        Command::Check { domain, json, no_cache } => check_domain(manager, &domain, json, no_cache)?,
+        Command::List { category, wide, max_reason_width, json, porcelain, show_target, index, template } => {
+            if porcelain {
+                list_porcelain(manager, category.as_deref())?
+            } else if let Some(template) = template {
+                list_template(manager, category.as_deref(), &template)?
+            } else {
+                list_domains(manager, category.as_deref(), wide, max_reason_width, json, show_target, index)?
+            }
+        }
+        Command::Backup { bundle } => bundle::backup(config, &bundle)?,
+        Command::Export { snapshot: Some(path), .. } => export::write_snapshot(config, &path)?,
+        // tld is the only way to split so far, --split-by and --output-dir require each other
+        Command::Export { format, split_by, output_dir, snapshot: None } => export::run(config, &format, split_by.and(output_dir).as_deref())?,
+        Command::Restore { bundle } => bundle::restore(config, &bundle)?,
+        Command::Stale { query_log, since, prune, yes } => stale::run(config, &query_log, &since, prune, yes)?,
+        Command::Prune { older_than, tag, reason, category, yes } => prune::run(config, &older_than, &prune::PruneFilter { tag, reason, category }, yes)?,
+        Command::Doctor { fix, yes, shared_reasons } => doctor(config, fix, yes, shared_reasons)?,
+        Command::Selftest => selftest(config)?,
+        Command::Healthcheck => healthcheck(config)?,
+        Command::Stats { by_day, bucket, json } => {
+            let bucket = if by_day { Some("day".to_string()) } else { bucket };
+            match bucket {
+                Some(bucket) => timeline(config, &bucket, json)?,
+                None => stats(config, json)?,
+            }
+        }
+        Command::Status { json } => status(manager, json)?,
+        Command::Reload => reload_now(config)?,
+        Command::About { json } => about(json)?,
+        Command::Schema => out!("{}", serde_json::to_string_pretty(&bind_manager::reason_log_schema())?),
+        Command::History { limit } => history::show(config, limit)?,
+        Command::Audit { since, until, domain, action, oldest_first, json } => {
+            let filter = history::AuditFilter::parse(since.as_deref(), until.as_deref(), domain.as_deref(), action)?;
+            history::audit(config, &filter, oldest_first, json)?
+        }
+        Command::Undo { steps } => history::undo(config, steps)?,
     }
 
     Ok(())
 }
 
-fn about() {
+fn about(json: bool) -> Result<()> {
+    let authors: Vec<&str> = env!("CARGO_PKG_AUTHORS").split(':').collect();
+    if json {
+        let about = serde_json::json!({
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "authors": authors,
+        });
+        out!("{}", serde_json::to_string_pretty(&about)?);
+        return Ok(());
+    }
+
     let top_heading = format!("--- {} v{} ---", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-    println!("{}", top_heading);
-    println!("This tool was created to aid in managing BIND blacklisted zones - making it easier to add, remove, and list domains that are blocked by the DNS server.\nIt's meant to be simple and efficient, and it uses a JSON file to store the reasons for blacklisting domains.");
-    println!("\nAuthors: {}", env!("CARGO_PKG_AUTHORS").split(':').collect::<Vec<&str>>().join(", "));
-    println!("{}", top_heading.chars().map(|_| "-").collect::<String>());
+    out!("{}", top_heading);
+    out!("This tool was created to aid in managing BIND blacklisted zones - making it easier to add, remove, and list domains that are blocked by the DNS server.\nIt's meant to be simple and efficient, and it uses a JSON file to store the reasons for blacklisting domains.");
+    out!("\nAuthors: {}", authors.join(", "));
+    out!("{}", top_heading.chars().map(|_| "-").collect::<String>());
+    Ok(())
+}
+
+// Refuses to block a protected or known-good domain (or a parent of one), only warning with force
+fn check_blockable(config: &Config, domain: &str, force: bool) -> Result<()> {
+    check_blockable_with(config, domain, known_good_by(config, domain)?.as_deref(), force)
 }
 
-fn load_reason_log() -> io::Result<Vec<DomainEntry>> {
-    if Path::new(REASON_LOG_PATH).exists() {
-        let file = fs::File::open(REASON_LOG_PATH)?;
-        let reader = BufReader::new(file);
-        match serde_json::from_reader(reader) {
-            Ok(entries) => Ok(entries),
-            Err(_) => Ok(Vec::new()),
+// Like check_blockable, with the known-good domain it would block already looked up, for
+// checking many domains against a `KnownGood` loaded once
+fn check_blockable_with(config: &Config, domain: &str, known_good: Option<&str>, force: bool) -> Result<()> {
+    let domain = normalize_domain(domain);
+    if let Some(protected) = protected_by(config, &domain) {
+        let local = config.local_domain().filter(|(local, _)| normalize_domain(local) == protected);
+        let what = match &local {
+            Some((_, source)) => format!("the local domain (from {})", source),
+            None => "a protected domain".to_string(),
+        };
+        let why = match domain == protected {
+            true => format!("{} is {}", domain, what),
+            false => format!("blocking {} also blocks {}, which is {}", domain, protected, what),
+        };
+        if !force {
+            return Err(Error::InvalidArgument(format!("Refusing to add it, {}. Pass --force if you really mean it.", why)));
         }
+        println!("!!! WARNING! {} !!!", why);
+    }
+    if let Some(known) = known_good {
+        let list = config.known_good_list.as_deref().unwrap_or(Path::new("")).display();
+        let why = match domain == known {
+            true => format!("{} is in the known-good list {}", domain, list),
+            false => format!("blocking {} also blocks {}, which is in the known-good list {}", domain, known, list),
+        };
+        if !force {
+            return Err(Error::InvalidArgument(format!("{}, are you sure? Pass --force to block it anyway.", why)));
+        }
+        println!("Warning! {}.", why);
+    }
+    Ok(())
+}
+
+fn read_reason_file(path: &Path) -> Result<String> {
+    // Reasons are shown on a single line, so fold the file's lines and whitespace together
+    let contents = fs::read_to_string(path).map_err(Error::reading(path))?;
+    let reason = contents.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    if reason.is_empty() {
+        return Err(Error::InvalidArgument(format!("Reason file {} is empty.", path.display())));
+    }
+    Ok(reason)
+}
+
+fn resolve_category(config: &Config, category: &str, force: bool) -> Result<String> {
+    if let Some(allowed) = config.find_category(category) {
+        return Ok(allowed.to_string());
+    }
+
+    if force {
+        println!("Warning! Category {} is not in the config, adding it anyway.", category);
+        return Ok(category.trim().to_string());
+    }
+
+    let message = if config.categories.is_empty() {
+        format!("Unknown category {}, no categories are configured in {} (use --force to add it anyway).", category, CONFIG_PATH)
     } else {
-        Ok(Vec::new())
+        format!("Unknown category {}, expected one of: {} (use --force to add it anyway).", category, config.categories.join(", "))
+    };
+    Err(Error::InvalidArgument(message))
+}
+
+// Pasted URLs (https://evil.com/path?x=1) and host:port pairs would render broken zones, so keep
+// just the hostname, or refuse to guess when strict
+fn hostname_from_input(input: &str, strict: bool) -> Result<String> {
+    let trimmed = input.trim();
+    let mut host = trimmed.split_once("://").map_or(trimmed, |(_, rest)| rest);
+    host = host.split(['/', '?', '#']).next().unwrap_or_default();
+    host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    if let Some((name, port)) = host.rsplit_once(':') {
+        if !port.is_empty() && port.chars().all(|c| c.is_ascii_digit()) {
+            host = name;
+        }
     }
+
+    if host == trimmed {
+        return Ok(trimmed.to_string());
+    }
+    if strict {
+        return Err(Error::InvalidArgument(format!("{} is not a bare domain, did you mean {}?", trimmed, host)));
+    }
+    println!("Warning! Interpreted {} as the domain {}.", trimmed, host);
+    Ok(host.to_string())
 }
 
-fn save_reason_log(entries: &Vec<DomainEntry>) -> io::Result<()> {
-    let file = OpenOptions::new().write(true).truncate(true).create(true).open(REASON_LOG_PATH)?;
-    serde_json::to_writer(file, &entries)?;
+// Suffixes that so much of the internet lives under that blocking one is almost certainly a mistake
+const BROAD_SUFFIXES: &[&str] = &[
+    "com", "net", "org", "edu", "gov", "mil", "int", "info", "io", "co", "app", "dev", "uk", "co.uk", "de", "fr", "nl", "jp", "cn", "ru",
+    "br", "in", "au", "ca", "us", "eu",
+];
+
+// Accepts .zip, *.zip or zip for a suffix block, returning None if blocking a broad suffix wasn't confirmed
+fn suffix_from_input(input: &str, yes: bool) -> Result<Option<String>> {
+    let suffix = normalize_domain(input.trim().trim_start_matches("*.").trim_start_matches('.'));
+    validate_domain(&suffix)?;
+    let question = format!("This blocks every domain under .{}, which is a widely used suffix.", suffix);
+    if BROAD_SUFFIXES.contains(&suffix.as_str()) && !confirm::confirm(&question, yes)? {
+        return Ok(None);
+    }
+    Ok(Some(suffix))
+}
+
+fn preview(config: &Config, domain: &str, zone_file: Option<&str>) -> Result<()> {
+    let domain = normalize_domain(domain);
+    validate_domain(&domain)?;
+    out!("{}", render_zone(config, &domain, zone_file)?);
     Ok(())
 }
 
-fn add_domain(domain: &str, reason: &str) -> io::Result<()> {
-    let mut entries = load_reason_log()?;
+fn add_domain(manager: &BindManager, new_entry: DomainEntry) -> Result<()> {
+    report_add(manager.config(), manager.add(new_entry)?)
+}
 
-    // Check if the domain already exists
-    if let Some(entry) = entries.iter_mut().find(|entry| entry.domain == domain) {
-        // Update the reason for the existing domain
-        entry.reason = reason.to_string();
-        println!("Record already exists, updated reason for domain {}.", domain);
-    } else {
-        // Add the new domain entry
-        let entry = DomainEntry { domain: domain.to_string(), reason: reason.to_string() };
-        entries.push(entry);
+// The declarative add for configuration management: blocks the domain with the reason unless it
+// already is, leaving everything else recorded for it alone. It succeeds either way and only the
+// message says whether anything changed, so repeated runs never fail or report a spurious change.
+fn ensure_domain(config: &Config, domain: &str, reason: String) -> Result<()> {
+    let domain = normalize_domain(domain);
+    validate_domain(&domain)?;
+    check_blockable(config, &domain, false)?;
 
-        // Append the domain to the zones file
-        let entry_format = format!("zone \"{}\" {{type master; file \"{}\";}};\n\n", domain, BLACKLIST_ZONE);
-        let mut file = OpenOptions::new().append(true).open(ZONES_FILE_PATH)?;
-        file.write_all(entry_format.as_bytes())?;
+    // A workspace rather than add, so a zone without a reason log entry (or the other way round) is completed instead of duplicated
+    let mut workspace = batch::Workspace::load(config)?;
+    let blocked = workspace.domains().contains(&domain);
+    let entry = match workspace.entry(&domain) {
+        Some(entry) => DomainEntry { reason, active: true, ..entry.clone() },
+        None => DomainEntry { reason, ..DomainEntry::new(config, &domain) },
+    };
+    if !workspace.add(entry)? {
+        println!("Domain {} ensured (no change).", domain);
+        logging::event(Level::Info, "ensure", Some(&domain), "unchanged", None);
+        return Ok(());
+    }
 
-        println!("Domain {} added to blacklist.", domain);
+    workspace.flush()?;
+    let outcome = if blocked { "updated" } else { "created" };
+    println!("Domain {} ensured ({}).", domain, outcome);
+    logging::event(Level::Info, "ensure", Some(&domain), outcome, None);
+    // Only the reason log changed for an update, which BIND doesn't read
+    if !blocked {
+        reload_bind(config)?;
     }
+    Ok(())
+}
+
+fn activate_domain(manager: &BindManager, domain: &str) -> Result<()> {
+    report_add(manager.config(), manager.activate(domain)?)
+}
 
-    // Save the updated entries back to the reason_log.json file
-    save_reason_log(&entries)?;
-    reload_bind()?;
+fn report_add(config: &Config, report: AddReport) -> Result<()> {
+    for warning in &report.warnings {
+        println!("Warning! {}", warning);
+    }
+    if report.catch_all_added {
+        report_catch_all(config);
+    }
 
+    match report.outcome {
+        AddOutcome::Added => {
+            summary::added(1);
+            println!("Domain {} added to {}.", report.domain, config.list_name())
+        }
+        AddOutcome::Updated => {
+            summary::updated(1);
+            println!("Record already exists, updated reason for domain {}.", report.domain)
+        }
+        // Nothing to write or reload if the reason is unchanged
+        AddOutcome::Unchanged => {
+            println!("No change, domain {} is already in the {} with that reason.", report.domain, config.list_name());
+            return Ok(());
+        }
+        // Staged entries have no zone yet, so there is nothing to reload
+        AddOutcome::Staged => {
+            println!("Domain {} staged, it is not blocked until you run `activate {}`.", report.domain, report.domain);
+            return Ok(());
+        }
+        AddOutcome::Commented => {
+            report_commented(config, &report.domain);
+            return Ok(());
+        }
+    }
+    reload_bind(config)
+}
+
+fn report_commented(config: &Config, domain: &str) {
+    println!(
+        "Warning! Domain {} is commented out in {}, leaving it alone (commented_zones is present). Uncomment it to block it again.",
+        domain,
+        config.zones_file.display()
+    );
+}
+
+// Changes the reason or note of a blocked domain; only the reason log is touched, so BIND isn't reloaded
+fn edit_domain(config: &Config, domain: &str, reason: Option<String>, note: Option<String>) -> Result<()> {
+    if reason.is_none() && note.is_none() {
+        return Err(Error::InvalidArgument("Nothing to edit, pass --reason and/or --note.".to_string()));
+    }
+
+    let domain = normalize_domain(domain);
+    let mut entries = load_reason_log(config)?;
+    let index = match entries.iter().position(|entry| normalize_domain(&entry.domain) == domain) {
+        Some(index) => index,
+        None => {
+            // A zone without a reason log entry can still be annotated
+            let blocked = read_zone_domains(config)?.iter().any(|zone| normalize_domain(zone) == domain);
+            if !blocked {
+                return Err(Error::NotFound(format!("Domain {} not found.", domain)));
+            }
+            entries.push(DomainEntry::new(config, &domain));
+            entries.len() - 1
+        }
+    };
+
+    let entry = &mut entries[index];
+    if let Some(reason) = reason.filter(|reason| *reason != entry.reason) {
+        entry.reason = reason;
+        entry.reason_updated_at = Some(Utc::now());
+    }
+    if let Some(note) = note {
+        entry.note = note;
+    }
+
+    if !save_reason_log(config, &entries)? {
+        println!("Domain {} is already up to date.", domain);
+        return Ok(());
+    }
+    println!("Updated domain {}.", domain);
+    logging::event(Level::Info, "edit", Some(&domain), "updated", None);
+
+    Ok(())
+}
+
+// Reasons typed by different people drift in case and spacing, so compare them folded
+fn fold_reason(reason: &str) -> String {
+    reason.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase()
+}
+
+// Rewrites the reason log in `format` from whatever format it is in now, BIND never reads it so nothing is reloaded
+fn convert_reason_log(config: &Config, format: &str) -> Result<()> {
+    let format = ReasonLogFormat::parse(format)
+        .ok_or_else(|| Error::InvalidArgument(format!("Unknown reason log format {}, expected json, jsonl or toml.", format)))?;
+    let path = config.reason_log.as_path();
+    let contents = config.backend.read(path).map_err(Error::reading(path))?;
+    let current = ReasonLogFormat::detect(&contents).unwrap_or_default();
+    if current == format {
+        println!("{} is already {}, nothing to convert.", path.display(), format.as_str());
+        return Ok(());
+    }
+
+    // Decoded as the format it is in now, which load_reason_log refuses while it differs from the declared one
+    let entries = current.decode(&contents)?;
+    save_reason_log_as(config, format, &entries)?;
+    println!("Converted {} entries in {} from {} to {}.", entries.len(), path.display(), current.as_str(), format.as_str());
+    if let Some(declared) = config.reason_log_format.filter(|declared| *declared != format) {
+        println!("Warning! reason_log_format in {} is still {}, set it to {} or remove it.", CONFIG_PATH, declared.as_str(), format.as_str());
+    }
+    logging::event(Level::Info, "convert-reason-log", None, "converted", Some(format.as_str()));
+    Ok(())
+}
+
+// Rewrites every reason that matches `from` once folded to `to`
+fn relabel(config: &Config, from: &str, to: &str) -> Result<()> {
+    let from = fold_reason(from);
+    let mut entries = load_reason_log(config)?;
+    let mut relabeled = 0;
+    for entry in entries.iter_mut().filter(|entry| fold_reason(&entry.reason) == from && entry.reason != to) {
+        entry.reason = to.to_string();
+        entry.reason_updated_at = Some(Utc::now());
+        relabeled += 1;
+        logging::event(Level::Info, "relabel", Some(&normalize_domain(&entry.domain)), "updated", None);
+    }
+
+    if relabeled > 0 {
+        save_reason_log(config, &entries)?;
+    }
+    summary::updated(relabeled);
+    println!("Relabeled {} {}.", relabeled, if relabeled == 1 { "domain" } else { "domains" });
     Ok(())
 }
 
-fn remove_domain(domain: &str) -> io::Result<()> {
-    let mut entries = load_reason_log()?;
-    let index = entries.iter().position(|entry| entry.domain == domain);
+// Turns a soft block into a hard block by pointing its zone at the sinkhole
+fn promote_domain(config: &Config, domain: &str) -> Result<()> {
+    let domain = normalize_domain(domain);
+    let mut entries = load_reason_log(config)?;
+    let entry = match entries.iter_mut().find(|entry| normalize_domain(&entry.domain) == domain) {
+        Some(entry) => entry,
+        None => return Err(Error::NotFound(format!("Domain {} not found.", domain))),
+    };
+    // Writing its zone would activate it too, skipping whatever it is staged for
+    if !entry.active {
+        return Err(Error::InvalidArgument(format!("Domain {} is staged, activate it before promoting it.", domain)));
+    }
+    if entry.mode == BlockMode::Block {
+        println!("Domain {} is already blocked.", domain);
+        return Ok(());
+    }
+
+    entry.mode = BlockMode::Block;
+    let rendered = render_zone(config, &domain, entry.zone_file.as_deref())?;
 
-    if let Some(idx) = index {
-        entries.remove(idx);
-        save_reason_log(&entries)?;
+    let contents = read_zones_file(config)?;
+    let mut replaced = false;
+    let mut updated = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        match parse_domain_from_line(line) {
+            Some(zone) if normalize_domain(&zone) == domain => {
+                updated.push_str(&rendered);
+                replaced = true;
+            }
+            _ => updated.push_str(line),
+        }
+        updated.push('\n');
     }
+    if !replaced {
+        updated.push_str(&format!("{}\n\n", rendered));
+    }
+    let mut updated = updated.into_bytes();
+    ensure_header(config, &mut updated);
+    write_zones_file(config, &updated)?;
+    save_reason_log(config, &entries)?;
 
-    let path = Path::new(ZONES_FILE_PATH);
-    let file = OpenOptions::new().read(true).open(&path)?;
-    let reader = BufReader::new(file);
+    println!("Domain {} promoted from a soft block to a block.", domain);
+    logging::event(Level::Info, "promote", Some(&domain), "promoted", None);
+    reload_bind(config)
+}
 
-    // Collect lines once to avoid "value used after move" error
-    let all_lines: Vec<String> = reader.lines().filter_map(Result::ok).collect();
-    let filtered_lines: Vec<String> = all_lines.iter().filter(|line| !line.contains(domain)).cloned().collect();
+// Matches a domain against a shell-style pattern: * is any run of characters, ? is any single one
+fn matches_pattern(pattern: &str, domain: &str) -> bool {
+    let pattern: Vec<char> = normalize_domain(pattern).chars().collect();
+    let domain: Vec<char> = domain.chars().collect();
 
-    if filtered_lines.len() < all_lines.len() {
-        fs::write(&path, filtered_lines.join("\n"))?;
-        println!("Domain {} removed from blacklist.", domain);
-    } else if index.is_none() {
-        println!("Domain not found.");
+    let (mut p, mut d) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while d < domain.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == domain[d]) {
+            p += 1;
+            d += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, d));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            // Let the last * swallow one more character and try again
+            p = star + 1;
+            d = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Adds and removes tags on every reason log entry matching the pattern; the zones file is untouched
+fn tag_domains(config: &Config, pattern: &str, add: &[String], remove: &[String]) -> Result<()> {
+    if add.is_empty() && remove.is_empty() {
+        return Err(Error::InvalidArgument("Nothing to do, pass --add and/or --remove.".to_string()));
     }
 
-    reload_bind()?;
+    let mut entries = load_reason_log(config)?;
+    let mut matched = 0;
+    let mut changed = 0;
+    for entry in entries.iter_mut().filter(|entry| matches_pattern(pattern, &normalize_domain(&entry.domain))) {
+        matched += 1;
+        let before = entry.tags.clone();
+        entry.tags.retain(|tag| !remove.contains(tag));
+        for tag in add {
+            if !entry.tags.contains(tag) {
+                entry.tags.push(tag.clone());
+            }
+        }
+        if entry.tags != before {
+            changed += 1;
+        }
+    }
+
+    if changed > 0 {
+        save_reason_log(config, &entries)?;
+    }
+    summary::updated(changed);
+    println!("Updated tags on {} of {} matching {}.", changed, matched, if matched == 1 { "domain" } else { "domains" });
+    logging::event(Level::Info, "tag", None, "updated", Some(&format!("{} of {} matching {} changed", changed, matched, pattern)));
 
     Ok(())
 }
 
-fn reload_bind() -> io::Result<()> {
-    // Check if rndc exists
-    let check_rndc = std::process::Command::new("sh")
-        .arg("-c")
-        .arg("which rndc")
-        .output()?;
+fn show_domain(config: &Config, domain: &str, json: bool) -> Result<()> {
+    let domain = normalize_domain(domain);
+    let blocked = read_zone_domains(config)?.iter().any(|zone| normalize_domain(zone) == domain);
+    let entries = load_reason_log(config)?;
+    let entry = entries.into_iter().find(|entry| normalize_domain(&entry.domain) == domain);
 
-    if !check_rndc.status.success() {
-        println!("Warning! RNDC does not exist - will skip reload.");
+    let entry = match (blocked, entry) {
+        (false, None) => return Err(Error::NotFound(format!("Domain {} not found.", domain))),
+        (_, Some(entry)) => entry,
+        (true, None) => DomainEntry::new(config, &domain),
+    };
+
+    if json {
+        // The entry as list --json shows it, plus whether it is actually blocked
+        let mut value = serde_json::to_value(&entry)?;
+        value["domain"] = serde_json::json!(domain);
+        value["blocked"] = serde_json::json!(blocked);
+        out!("{}", serde_json::to_string(&value)?);
         return Ok(());
     }
 
-    // Existing logic to reload BIND
-    let output = std::process::Command::new("rndc").arg("reload").output()?;
-    if output.status.success() {
-        println!("BIND reloaded successfully.");
+    out!("Domain:   {}", domain);
+    let blocked = match (blocked, entry.mode) {
+        (false, _) => "no (only in the reason log)",
+        (true, BlockMode::Block) => "yes",
+        (true, BlockMode::Log) => "soft block, logged but not sinkholed",
+    };
+    out!("Blocked:  {}", blocked);
+    out!("Reason:   {}", entry.reason);
+    if let Some(category) = &entry.category {
+        out!("Category: {}", category);
+    }
+    if !entry.tags.is_empty() {
+        out!("Tags:     {}", format_tags(&entry.tags));
+    }
+    if let Some(severity) = entry.severity {
+        out!("Severity: {}", severity.as_str());
+    }
+    if let Some(source) = &entry.source {
+        out!("Source:   {}", source);
+    }
+    if let Some(reference_url) = &entry.reference_url {
+        out!("Ref:      <{}>", reference_url);
+    }
+    if let Some(added_at) = entry.added_at {
+        out!("Added:    {}", added_at.format("%Y-%m-%d %H:%M:%S UTC"));
+    }
+    if let Some(reason_updated_at) = entry.reason_updated_at {
+        out!("Changed:  {} (reason last edited)", reason_updated_at.format("%Y-%m-%d %H:%M:%S UTC"));
+    }
+    if !entry.note.is_empty() {
+        out!("Note:");
+        for line in entry.note.lines() {
+            out!("    {}", line);
+        }
+    }
+
+    Ok(())
+}
+
+fn remove_domain(manager: &BindManager, domain: &str) -> Result<()> {
+    // Nothing was written if the domain wasn't there, so there's nothing for BIND to pick up
+    manager.remove(domain)?;
+    summary::removed(1);
+    println!("Domain {} removed from {}.", normalize_domain(domain), manager.config().list_name());
+    reload_bind(manager.config())
+}
+
+// Identifies a listing by its domains in order, so del-index can tell the list changed since it was shown
+fn listing_fingerprint(listing: &Listing) -> String {
+    let mut hasher = Sha256::new();
+    for entry in &listing.entries {
+        hasher.update(entry.domain.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// Removes the nth domain of the last `list --index`, refusing if the list has changed since then
+fn remove_by_index(manager: &BindManager, n: usize) -> Result<()> {
+    let config = manager.config();
+    let Some(record) = StateFile::load(config).last_listing.filter(|record| record.zones_file == config.zones_file) else {
+        return Err(Error::InvalidArgument("No numbered listing to pick from, run list --index first.".to_string()));
+    };
+    let listing = manager.list(record.category.as_deref())?;
+    if listing_fingerprint(&listing) != record.fingerprint {
+        return Err(Error::InvalidArgument(format!(
+            "The list changed since list --index showed it at {}, run it again to see the current numbers.",
+            record.at.format("%Y-%m-%d %H:%M:%S UTC")
+        )));
+    }
+    if n == 0 || n > listing.entries.len() {
+        return Err(Error::InvalidArgument(format!(
+            "There is no domain {} in the last listing, it numbered {} {}.",
+            n,
+            listing.entries.len(),
+            if listing.entries.len() == 1 { "domain" } else { "domains" }
+        )));
+    }
+    remove_domain(manager, &listing.entries[n - 1].domain)
+}
+
+// Every domain with a zone or a reason log entry, i.e. everything a removal could touch
+fn known_domains(config: &Config) -> Result<HashSet<String>> {
+    let mut known: HashSet<String> = read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).collect();
+    known.extend(load_reason_log(config)?.iter().map(|entry| normalize_domain(&entry.domain)));
+    Ok(known)
+}
+
+fn remove_matching(config: &Config, pattern: &str, yes: bool) -> Result<()> {
+    let pattern = normalize_domain(pattern);
+    let mut matched: Vec<String> = known_domains(config)?.into_iter().filter(|domain| matches_pattern(&pattern, domain)).collect();
+    matched.sort();
+
+    if matched.is_empty() {
+        return Err(Error::NotFound(format!("No domains match {}.", pattern)));
+    }
+    if !confirm::confirm_removal(&matched, yes)? {
+        println!("Nothing removed.");
+        return Ok(());
+    }
+
+    let before = read_zone_domains(config)?.len();
+    let removed = remove_domain_set(config, &matched.into_iter().collect())?;
+    summary::removed(removed.len());
+    for domain in &removed {
+        logging::event(Level::Info, "del-match", Some(domain), "removed", None);
+    }
+    println!("Removed {} {} matching {}.", removed.len(), if removed.len() == 1 { "domain" } else { "domains" }, pattern);
+    report_count_delta(config, before)?;
+    reload_bind(config)
+}
+
+// Removes a registrable domain and every blocked name under it, e.g. once a malicious domain is taken down
+fn remove_domain_tree(config: &Config, domain: &str, yes: bool, dry_run: bool) -> Result<()> {
+    let domain = normalize_domain(domain);
+    let registrable = match psl::domain_str(&domain) {
+        Some(registrable) => registrable.to_string(),
+        None => return Err(Error::InvalidArgument(format!("{} is a public suffix, not a registrable domain.", domain))),
+    };
+    let below = format!(".{}", registrable);
+    let mut tree: Vec<String> = known_domains(config)?.into_iter().filter(|known| *known == registrable || known.ends_with(&below)).collect();
+    tree.sort();
+    if tree.is_empty() {
+        return Err(Error::NotFound(format!("Nothing under {} is blocked.", registrable)));
+    }
+
+    let count = format!("{} {}", tree.len(), if tree.len() == 1 { "domain" } else { "domains" });
+    println!("{} {} under {}:", if dry_run { "Would remove" } else { "Removing" }, count, registrable);
+    for domain in &tree {
+        println!(" - {}", domain);
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if !confirm::confirm(&format!("This removes {} under {}.", count, registrable), yes)? {
+        println!("Nothing removed.");
+        return Ok(());
+    }
+
+    let before = read_zone_domains(config)?.len();
+    let removed = remove_domain_set(config, &tree.into_iter().collect())?;
+    summary::removed(removed.len());
+    for domain in &removed {
+        logging::event(Level::Info, "del-domain-tree", Some(domain), "removed", None);
+    }
+    println!("Removed {} {} under {}.", removed.len(), if removed.len() == 1 { "domain" } else { "domains" }, registrable);
+    report_count_delta(config, before)?;
+    reload_bind(config)
+}
+
+fn remove_file(config: &Config, file: &str, yes: bool) -> Result<()> {
+    let contents = fs::read_to_string(file).map_err(Error::reading(Path::new(file)))?;
+    let domains: HashSet<String> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(normalize_domain)
+        .collect();
+
+    let mut present: Vec<String> = known_domains(config)?.intersection(&domains).cloned().collect();
+    present.sort();
+    if !confirm::confirm_removal(&present, yes)? {
+        println!("Nothing removed.");
+        return Ok(());
+    }
+
+    let before = read_zone_domains(config)?.len();
+    let removed = remove_domain_set(config, &domains)?;
+    summary::removed(removed.len());
+
+    let mut not_found: Vec<&String> = domains.iter().filter(|domain| !removed.contains(*domain)).collect();
+    let mut removed_list: Vec<&String> = removed.iter().collect();
+    removed_list.sort();
+    not_found.sort();
+
+    if !removed_list.is_empty() {
+        println!("Removed {} {} from blacklist:", removed_list.len(), if removed_list.len() == 1 { "domain" } else { "domains" });
+        for domain in &removed_list {
+            println!(" - {}", domain);
+            logging::event(Level::Info, "remove-file", Some(domain), "removed", None);
+        }
+    }
+    if !not_found.is_empty() {
+        println!("{} {} not found:", not_found.len(), if not_found.len() == 1 { "domain was" } else { "domains were" });
+        for domain in &not_found {
+            println!(" - {}", domain);
+            logging::event(Level::Warning, "remove-file", Some(domain), "not found", None);
+        }
+    }
+
+    report_count_delta(config, before)?;
+
+    // Reload once for the whole file, and only if something changed
+    if !removed.is_empty() {
+        reload_bind(config)?;
+    }
+
+    Ok(())
+}
+
+fn check_domain(manager: &BindManager, domain: &str, json: bool, no_cache: bool) -> Result<()> {
+    let domain = normalize_domain(domain);
+    let found = if no_cache { manager.check_uncached(&domain)? } else { manager.check(&domain)? };
+    // Covered by a suffix block above it, rather than blocked itself
+    let covered = |entry: &DomainEntry| entry.suffix && entry.domain != domain;
+    match found {
+        // The exit code still says whether the domain is blocked, so callers can use either
+        Some(entry) if json => {
+            let mut value = serde_json::json!({"domain": domain, "blocked": true, "reason": entry.reason, "added_at": entry.added_at});
+            if covered(&entry) {
+                value["covered_by"] = serde_json::json!(format!(".{}", entry.domain));
+            }
+            out!("{}", value)
+        }
+        Some(entry) if covered(&entry) => out!(
+            "Domain {} is blacklisted by the suffix block .{} » {}",
+            domain,
+            entry.domain,
+            display_reason(manager.config(), &entry)
+        ),
+        None if json => {
+            out!("{}", serde_json::json!({"domain": domain, "blocked": false}));
+            return Err(Error::NotFound(format!("Domain {} is not blacklisted.", domain)));
+        }
+        Some(entry) => out!("Domain {} is blacklisted » {}", domain, display_reason(manager.config(), &entry)),
+        None => return Err(Error::NotFound(format!("Domain {} is not blacklisted.", domain))),
+    }
+
+    Ok(())
+}
+
+// Reloads BIND and says what happened, the CLI side of bind_manager::reload
+fn reload_bind(config: &Config) -> Result<()> {
+    report_reload(bind_manager::reload(config)?);
+    Ok(())
+}
+
+fn reload_now(config: &Config) -> Result<()> {
+    report_reload(bind_manager::reload_now(config)?);
+    Ok(())
+}
+
+fn report_reload(outcome: ReloadOutcome) {
+    match outcome {
+        ReloadOutcome::Reloaded => {
+            summary::reloaded();
+            println!("BIND reloaded successfully.")
+        }
+        ReloadOutcome::CommandNotFound { program } => println!(
+            "Warning! {} not found on PATH, the change was written but BIND was not reloaded. Set reload_command in {} or use --no-reload.",
+            program, CONFIG_PATH
+        ),
+        ReloadOutcome::Disabled => println!("The change was written, BIND was not reloaded (no_reload)."),
+        ReloadOutcome::Pending { elapsed } => {
+            println!("BIND was reloaded {}s ago, the reload is pending. Run `reload` to apply it now.", elapsed)
+        }
+    }
+}
+
+// Printed at the end of bulk operations so the size of the change can be sanity-checked
+fn report_count_delta(config: &Config, before: usize) -> Result<()> {
+    let after = read_zone_domains(config)?.len();
+    println!("Blocked domains: {} → {} ({:+})", before, after, after as i64 - before as i64);
+    Ok(())
+}
+
+// Printed whenever a write had to put the allowlist catch-all zone in place first
+fn report_catch_all(config: &Config) {
+    println!("Added the catch-all zone, everything not on the allowlist now resolves to {}.", config.zone_db);
+}
+
+// The last label of a domain, which stats and export group by
+fn tld_of(domain: &str) -> &str {
+    domain.trim_end_matches('.').rsplit('.').next().unwrap_or(domain)
+}
+
+// Counts per key, most common first
+fn tally<'a>(keys: impl Iterator<Item = &'a str>) -> Vec<(&'a str, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for key in keys {
+        *counts.entry(key).or_default() += 1;
+    }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    counts
+}
+
+// With json a single object with stable keys, for monitoring to collect on a schedule
+fn stats(config: &Config, json: bool) -> Result<()> {
+    let domains = read_zone_domains(config)?;
+    let total = domains.len();
+    let entries = load_reason_log(config)?;
+
+    if json {
+        let counts = |counts: Vec<(&str, usize)>| {
+            serde_json::Value::Object(counts.into_iter().map(|(key, count)| (key.to_string(), count.into())).collect())
+        };
+        // Counted per blocked zone, so zones without a reason log entry count with the defaults
+        let by_domain: HashMap<String, &DomainEntry> = entries.iter().map(|entry| (normalize_domain(&entry.domain), entry)).collect();
+        let entry_of = |domain: &String| by_domain.get(&normalize_domain(domain)).copied();
+        let reason_of = |domain: &String| entry_of(domain).map_or(config.default_reason.as_str(), |entry| entry.reason.as_str());
+        let category_of = |domain: &String| entry_of(domain).and_then(|entry| entry.category.as_deref()).unwrap_or("(none)");
+        let severity_of = |domain: &String| entry_of(domain).and_then(|entry| entry.severity).map_or("(none)", |severity| severity.as_str());
+        let stats = serde_json::json!({
+            "total": total,
+            "by_tld": counts(tally(domains.iter().map(|domain| tld_of(domain)))),
+            "by_reason": counts(tally(domains.iter().map(reason_of))),
+            "by_category": counts(tally(domains.iter().map(category_of))),
+            "by_severity": counts(tally(domains.iter().map(severity_of))),
+            "default_reason": domains.iter().filter(|domain| reason_of(domain) == config.default_reason).count(),
+        });
+        out!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    out!("Blocked domains: {}", total);
+
+    let by_category = tally(entries.iter().map(|entry| entry.category.as_deref().unwrap_or("(none)")));
+
+    out!("By category:");
+    let max_len = by_category.iter().map(|(category, _)| category.len()).max().unwrap_or(0);
+    for (category, count) in &by_category {
+        out!(" - {:<width$} {}", category, count, width = max_len);
+    }
+
+    let undated = entries.iter().filter(|entry| entry.added_at.is_none()).count();
+    if undated > 0 {
+        out!("{} {} no recorded date.", undated, if undated == 1 { "entry has" } else { "entries have" });
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct TimelineBucket {
+    bucket: String,
+    count: usize,
+}
+
+// Returns the first day of the bucket a date falls into, and how to label it
+fn bucket_start(date: NaiveDate, bucket: &str) -> (NaiveDate, String) {
+    match bucket {
+        "week" => {
+            let start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+            (start, start.format("%G-W%V").to_string())
+        }
+        "month" => {
+            let start = date.with_day(1).unwrap_or(date);
+            (start, start.format("%Y-%m").to_string())
+        }
+        _ => (date, date.format("%Y-%m-%d").to_string()),
+    }
+}
+
+fn next_bucket(start: NaiveDate, bucket: &str) -> NaiveDate {
+    match bucket {
+        "week" => start + Duration::days(7),
+        "month" => start.checked_add_months(Months::new(1)).unwrap_or(start + Duration::days(31)),
+        _ => start + Duration::days(1),
+    }
+}
+
+fn timeline(config: &Config, bucket: &str, json: bool) -> Result<()> {
+    let entries = load_reason_log(config)?;
+
+    let mut counts: HashMap<NaiveDate, usize> = HashMap::new();
+    for added_at in entries.iter().filter_map(|entry| entry.added_at) {
+        *counts.entry(bucket_start(added_at.date_naive(), bucket).0).or_default() += 1;
+    }
+
+    // Fill in the quiet periods too, so the series can be charted as-is
+    let mut buckets = Vec::new();
+    if let (Some(first), Some(last)) = (counts.keys().min().copied(), counts.keys().max().copied()) {
+        let mut current = first;
+        while current <= last {
+            let (_, label) = bucket_start(current, bucket);
+            buckets.push(TimelineBucket { bucket: label, count: counts.get(&current).copied().unwrap_or(0) });
+            current = next_bucket(current, bucket);
+        }
+    }
+
+    if json {
+        out!("{}", serde_json::to_string_pretty(&buckets)?);
+        return Ok(());
+    }
+
+    out!("Domains added per {}:", bucket);
+    let max_count = buckets.iter().map(|b| b.count).max().unwrap_or(0);
+    let max_len = buckets.iter().map(|b| b.bucket.len()).max().unwrap_or(0);
+    for b in &buckets {
+        // Scale the bars so the busiest bucket is 40 characters wide
+        let bar_len = if max_count == 0 { 0 } else { (b.count * 40).div_ceil(max_count) };
+        let line = format!(" {:<width$} {:>6} {}", b.bucket, b.count, "#".repeat(bar_len), width = max_len);
+        out!("{}", line.trim_end());
+    }
+
+    Ok(())
+}
+
+// The state of the manager at a glance: the list, its consistency, and how the last reload went
+fn status(manager: &BindManager, json: bool) -> Result<()> {
+    let config = manager.config();
+    let state = StateFile::load(config).reloads(&config.reload_key());
+    let format_time = |time: &DateTime<Utc>| time.format("%Y-%m-%d %H:%M:%S UTC").to_string();
+
+    // A quick doctor: lines it would flag, reasons without a zone, and missing zone db files
+    let listing = manager.list(None)?;
+    let zone_files = zone_file_overrides(config)?;
+    let mut suspicious = 0;
+    for source in zones::read_sources(config.backend.as_ref(), &config.zones_file)? {
+        suspicious += source.contents.lines().filter(|line| check_zone_line(config, &zone_files, line).is_some()).count();
+    }
+    let missing_zone_dbs = if config.forwards_listed() || config.output == OutputFormat::Hosts {
+        0
+    } else {
+        zone_db_targets(config, &load_reason_log(config)?).iter().filter(|target| check_zone_db(config, target).is_some()).count()
+    };
+    let consistent = suspicious == 0 && listing.orphaned.is_empty() && missing_zone_dbs == 0;
+
+    // The size of the zones file itself, for capacity planning. Far more lines than zones hints at
+    // formatting problems.
+    let path = config.zones_file.as_path();
+    let contents = config.backend.read(path).map_err(Error::reading(path))?;
+    let text = String::from_utf8_lossy(&contents);
+    let lines = text.lines().count();
+    let zone_lines = text.lines().filter(|line| parse_domain_from_line(line).is_some()).count();
+    let comment_lines = text.lines().filter(|line| is_comment_or_blank(line)).count();
+
+    if json {
+        let status = serde_json::json!({
+            "profile": config.profile,
+            "list": config.list_name(),
+            "read_only": config.read_only,
+            "no_reload": config.no_reload,
+            "blocked": listing.entries.len(),
+            "consistent": consistent,
+            "suspicious_lines": suspicious,
+            "orphaned_reasons": listing.orphaned.len(),
+            "missing_zone_dbs": missing_zone_dbs,
+            "zones_file_bytes": contents.len(),
+            "zones_file_lines": lines,
+            "zone_lines": zone_lines,
+            "comment_lines": comment_lines,
+            "other_lines": lines - zone_lines - comment_lines,
+            "last_reload": state.last_reload,
+            "last_successful_reload": state.last_successful_reload,
+            "reload_pending": state.reload_pending,
+        });
+        out!("{}", serde_json::to_string_pretty(&status)?);
+        return Ok(());
+    }
+
+    out!("Profile:  {}", config.profile.as_deref().unwrap_or("(default)"));
+    let mut flags = Vec::new();
+    if config.read_only {
+        flags.push("read-only");
+    }
+    if config.no_reload {
+        flags.push("no reload");
+    }
+    let flags = if flags.is_empty() { String::new() } else { format!(" ({})", flags.join(", ")) };
+    out!("List:     {} with {} blocked {}{}", config.list_name(), listing.entries.len(), if listing.entries.len() == 1 { "domain" } else { "domains" }, flags);
+    if consistent {
+        out!("Files:    consistent");
+    } else {
+        out!(
+            "Files:    {} suspicious zone lines, {} reasons without a zone, {} missing zone db files; run doctor for details",
+            suspicious,
+            listing.orphaned.len(),
+            missing_zone_dbs
+        );
+    }
+    let other_lines = lines - zone_lines - comment_lines;
+    let other = if other_lines > 0 { format!(", {} other", other_lines) } else { String::new() };
+    out!(
+        "Size:     {} bytes, {} {} ({} {}, {} comments or blank{})",
+        contents.len(),
+        lines,
+        if lines == 1 { "line" } else { "lines" },
+        zone_lines,
+        if zone_lines == 1 { "zone" } else { "zones" },
+        comment_lines,
+        other
+    );
+
+    match &state.last_reload {
+        None => out!("No reload has been recorded yet."),
+        Some(reload) if reload.success => out!("Last reload: {} (succeeded)", format_time(&reload.at)),
+        Some(reload) => {
+            out!("!!! LAST RELOAD FAILED at {} !!!", format_time(&reload.at));
+            if let Some(message) = &reload.message {
+                out!("    {}", message);
+            }
+            out!("BIND may be serving an older blacklist than the one on disk.");
+            match &state.last_successful_reload {
+                Some(time) => out!("Last successful reload: {}", format_time(time)),
+                None => out!("Last successful reload: never"),
+            }
+        }
+    }
+    if state.reload_pending {
+        out!("A reload is pending, run `reload` to apply the latest changes.");
+    }
+    Ok(())
+}
+
+fn display_reason(config: &Config, entry: &DomainEntry) -> String {
+    let reason = match &entry.category {
+        Some(category) if entry.reason == config.default_reason => format!("[{}]", category),
+        Some(category) => format!("[{}] {}", category, entry.reason),
+        None => entry.reason.clone(),
+    };
+    match entry.mode {
+        BlockMode::Block => reason,
+        BlockMode::Log => format!("(soft) {}", reason),
+    }
+}
+
+fn format_tags(tags: &[String]) -> String {
+    tags.join(",")
+}
+
+// Shortens text to at most `width` terminal columns (wide characters take two), marking the cut with an ellipsis
+fn truncate(text: &str, width: usize) -> String {
+    if text.width() <= width {
+        return text.to_string();
+    }
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        used += c.width().unwrap_or(0);
+        if used > width.saturating_sub(1) {
+            break;
+        }
+        truncated.push(c);
+    }
+    truncated.push('…');
+    truncated
+}
+
+// Left-aligns text in `width` terminal columns; format!'s padding counts characters instead
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(text.width())))
+}
+
+fn list_domains(
+    manager: &BindManager,
+    category: Option<&str>,
+    wide: bool,
+    max_reason_width: Option<usize>,
+    json: bool,
+    show_target: bool,
+    index: bool,
+) -> Result<()> {
+    let config = manager.config();
+    let listing = manager.list(category)?;
+    report_listing_warnings(&listing);
+    let orphaned = &listing.orphaned;
+    if index {
+        StateFile::record_listing(config, ListingRecord {
+            zones_file: config.zones_file.clone(),
+            category: category.map(str::to_string),
+            fingerprint: listing_fingerprint(&listing),
+            at: Utc::now(),
+        });
+    }
+
+    if json {
+        out!("{}", serde_json::to_string_pretty(&listing.entries)?);
+        if !orphaned.is_empty() {
+            eprintln!("Warning! {} reason log {} no zone in the zones file.", orphaned.len(), if orphaned.len() == 1 { "entry has" } else { "entries have" });
+        }
+        return Ok(());
+    }
+
+    // Build the table rows: optionally the index, domain, reason, added, optionally the target, and tags
+    let rows: Vec<Vec<String>> = listing
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| {
+            let mut row = Vec::new();
+            if index {
+                row.push((idx + 1).to_string());
+            }
+            row.extend([
+                if entry.suffix { format!(".{}", entry.domain) } else { entry.domain.clone() },
+                display_reason(config, entry),
+                entry.added_at.map(|added| added.format("%Y-%m-%d").to_string()).unwrap_or_else(|| "-".to_string()),
+            ]);
+            if show_target {
+                row.push(zone_target(config, entry));
+            }
+            row.push(format_tags(&entry.tags));
+            row
+        })
+        .collect();
+
+    let mut headers = if index { vec!["#"] } else { Vec::new() };
+    headers.extend(["DOMAIN", "REASON", "ADDED"]);
+    if show_target {
+        headers.push("TARGET");
+    }
+    headers.push("TAGS");
+    let reason_column = if index { 2 } else { 1 };
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.width());
+        }
+    }
+
+    // Give the reason column whatever the terminal has left, unless --wide was given or it goes to a file
+    let terminal_width = match output::is_redirected() {
+        true => None,
+        false => terminal_size::terminal_size().map(|(terminal_size::Width(w), _)| w as usize),
+    };
+    if let (false, Some(terminal_width)) = (wide, terminal_width) {
+        let others = widths.iter().sum::<usize>() - widths[reason_column] + 3 * (widths.len() - 1);
+        widths[reason_column] = widths[reason_column].min(terminal_width.saturating_sub(others).max(headers[reason_column].len()));
+    }
+    if let Some(max_reason_width) = max_reason_width {
+        widths[reason_column] = widths[reason_column].min(max_reason_width.max(headers[reason_column].len()));
+    }
+
+    out!("Listing {} {}:", rows.len(), if rows.len() == 1 { "domain" } else { "domains" });
+    let print_row = |cells: &[&str]| {
+        let line = cells
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(idx, (cell, width))| if idx == reason_column { pad(&truncate(cell, *width), *width) } else { pad(cell, *width) })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        out!("{}", line.trim_end());
+    };
+
+    print_row(&headers);
+    out!("{}", widths.iter().map(|width| "-".repeat(*width)).collect::<Vec<_>>().join("-+-"));
+    for row in &rows {
+        print_row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+
+    if !listing.staged.is_empty() {
+        out!("");
+        out!("Staged, not blocked until activated ({}):", listing.staged.len());
+        for entry in &listing.staged {
+            out!(" - {} » {}", entry.domain, display_reason(config, entry));
+        }
+    }
+
+    if !orphaned.is_empty() {
+        note!("");
+        let (has, is) = if orphaned.len() == 1 { ("entry has", "is") } else { ("entries have", "are") };
+        note!("Warning! {} reason log {} no zone in the zones file and {} not blocked:", orphaned.len(), has, is);
+        for entry in orphaned {
+            note!(" - {} » {}", entry.domain, display_reason(config, entry));
+        }
+    }
+
+    Ok(())
+}
+
+// Where a domain's zone sends its queries: the zone db file, or the forwarders for forward zones
+fn zone_target(config: &Config, entry: &DomainEntry) -> String {
+    if config.forwards_listed() {
+        return format!("forward {}", config.forwarders.join(" "));
+    }
+    entry_zone_file(config, entry).ok().flatten().unwrap_or(&config.zone_db).to_string()
+}
+
+// On stderr, so the listing itself stays parseable
+fn report_listing_warnings(listing: &Listing) {
+    for warning in &listing.warnings {
+        eprintln!("Warning! {}", warning);
+    }
+    if !listing.warnings.is_empty() {
+        eprintln!("Run doctor to see the damaged lines.");
+    }
+    for (zone, logged) in &listing.respelled {
+        eprintln!("Warning! {} is spelled {} in the reason log, run sync to make them agree.", zone, logged);
+    }
+}
+
+// One `domain<TAB>reason<TAB>added_at` line per domain, with added_at in RFC 3339 or empty. This
+// format is a compatibility promise to scripts, so never change it cosmetically.
+fn list_porcelain(manager: &BindManager, category: Option<&str>) -> Result<()> {
+    let listing = manager.list(category)?;
+    report_listing_warnings(&listing);
+    for entry in listing.entries {
+        let reason: String = entry.reason.chars().map(|c| if c == '\t' || c == '\n' || c == '\r' { ' ' } else { c }).collect();
+        let added_at = entry.added_at.map(|added| added.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)).unwrap_or_default();
+        out!("{}\t{}\t{}", entry.domain, reason, added_at);
+    }
+    Ok(())
+}
+
+const TEMPLATE_FIELDS: &[&str] = &["domain", "reason", "category", "tags", "note", "source", "severity", "ref", "added_at", "target"];
+
+enum TemplatePiece {
+    Text(String),
+    Field(&'static str),
+}
+
+// Splits a --template into literal text and placeholders, refusing unknown or unclosed ones
+fn parse_template(template: &str) -> Result<Vec<TemplatePiece>> {
+    let invalid = |why: String| Error::InvalidArgument(format!("Invalid template: {}.", why));
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                text.push(c);
+            }
+            ('\\', Some('t')) => {
+                chars.next();
+                text.push('\t');
+            }
+            ('\\', Some('n')) => {
+                chars.next();
+                text.push('\n');
+            }
+            ('{', _) => {
+                let mut name = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        closed = true;
+                        break;
+                    }
+                    name.push(c);
+                }
+                if !closed {
+                    return Err(invalid(format!("{{{} is not closed", name)));
+                }
+                let Some(field) = TEMPLATE_FIELDS.iter().find(|field| **field == name) else {
+                    let known: Vec<String> = TEMPLATE_FIELDS.iter().map(|field| format!("{{{}}}", field)).collect();
+                    return Err(invalid(format!("unknown placeholder {{{}}}, use one of {}", name, known.join(", "))));
+                };
+                pieces.push(TemplatePiece::Text(std::mem::take(&mut text)));
+                pieces.push(TemplatePiece::Field(field));
+            }
+            ('}', _) => return Err(invalid("a } without its {, write }} for a literal one".to_string())),
+            _ => text.push(c),
+        }
+    }
+    pieces.push(TemplatePiece::Text(text));
+    Ok(pieces)
+}
+
+// One rendering of the --template per listed entry, for layouts no built-in format covers
+fn list_template(manager: &BindManager, category: Option<&str>, template: &str) -> Result<()> {
+    let config = manager.config();
+    let pieces = parse_template(template)?;
+    let listing = manager.list(category)?;
+    report_listing_warnings(&listing);
+    for entry in &listing.entries {
+        let mut line = String::new();
+        for piece in &pieces {
+            match piece {
+                TemplatePiece::Text(text) => line.push_str(text),
+                TemplatePiece::Field("domain") => line.push_str(&entry.domain),
+                TemplatePiece::Field("reason") => line.push_str(&entry.reason),
+                TemplatePiece::Field("category") => line.push_str(entry.category.as_deref().unwrap_or_default()),
+                TemplatePiece::Field("tags") => line.push_str(&format_tags(&entry.tags)),
+                TemplatePiece::Field("note") => line.push_str(&entry.note),
+                TemplatePiece::Field("source") => line.push_str(entry.source.as_deref().unwrap_or_default()),
+                TemplatePiece::Field("severity") => line.push_str(entry.severity.map(|severity| severity.as_str()).unwrap_or_default()),
+                TemplatePiece::Field("ref") => line.push_str(entry.reference_url.as_deref().unwrap_or_default()),
+                TemplatePiece::Field("added_at") => {
+                    line.push_str(&entry.added_at.map(|added| added.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)).unwrap_or_default())
+                }
+                TemplatePiece::Field(_) => line.push_str(&zone_target(config, entry)),
+            }
+        }
+        out!("{}", line);
+    }
+    Ok(())
+}
+
+// `zone_files` holds the per-domain zone db overrides from the reason log
+fn check_zone_line(config: &Config, zone_files: &HashMap<String, String>, line: &str) -> Option<String> {
+    if let Some(problem) = zones::junk_line(line) {
+        return Some(problem.to_string());
+    }
+    if is_comment_or_blank(line) || parse_include(line).is_some() {
+        return None;
+    }
+    if config.output == OutputFormat::Hosts {
+        return match parse_domain_from_line(line) {
+            Some(_) => None,
+            None => Some("not a hosts line written by bind_manager".to_string()),
+        };
+    }
+
+    let zone = match parse_zone_statement(line) {
+        Some(zone) => zone,
+        None => return Some("not a zone statement written by bind_manager".to_string()),
+    };
+
+    // In allowlist mode only the catch-all is a master zone, everything else is forwarded
+    let forwarded = if config.mode == Mode::Allowlist { zone.name != "." } else { config.forwards_listed() };
+    let expected_type = if forwarded { ZoneType::Forward.as_str() } else { ZoneType::Master.as_str() };
+    match zone.zone_type.as_deref() {
+        Some(zone_type) if zone_type == expected_type => {}
+        Some(other) => return Some(format!("zone \"{}\" has type {} (expected {})", zone.name, other, expected_type)),
+        None => return Some(format!("zone \"{}\" has no type", zone.name)),
+    }
+    if forwarded {
+        return None;
+    }
+
+    let expected = zone_files.get(&normalize_domain(&zone.name)).unwrap_or(&config.zone_db);
+    match zone.file.as_deref() {
+        Some(file) if file == expected => None,
+        Some(other) => Some(format!("zone \"{}\" points at {} (expected {})", zone.name, other, expected)),
+        None => Some(format!("zone \"{}\" has no file", zone.name)),
+    }
+}
+
+// The zone db file each domain with an override (or a soft block) is expected to point at
+fn zone_file_overrides(config: &Config) -> Result<HashMap<String, String>> {
+    Ok(load_reason_log(config)?
+        .into_iter()
+        .filter_map(|entry| Some((normalize_domain(&entry.domain), entry_zone_file(config, &entry).ok()??.to_string())))
+        .collect())
+}
+
+fn doctor(config: &Config, fix: bool, yes: bool, shared_reasons: bool) -> Result<()> {
+    let mut suspicious = 0;
+    let zone_files = zone_file_overrides(config)?;
+
+    for source in zones::read_sources(config.backend.as_ref(), &config.zones_file)? {
+        println!("Checking {}...", source.path.display());
+        for (idx, line) in source.contents.lines().enumerate() {
+            if let Some(problem) = check_zone_line(config, &zone_files, line) {
+                suspicious += 1;
+                println!(" - line {}: {}", idx + 1, problem);
+                match zones::junk_line(line) {
+                    Some(_) => println!("     {}", line.trim().chars().take(120).flat_map(char::escape_default).collect::<String>()),
+                    None => println!("     {}", line.trim()),
+                }
+            }
+        }
+    }
+
+    if suspicious == 0 {
+        println!("No suspicious lines found.");
     } else {
-        println!("Failed to reload BIND.");
+        println!("Found {} suspicious {}.", suspicious, if suspicious == 1 { "line" } else { "lines" });
+    }
+
+    check_targets(config, &zone_files)?;
+    check_domain_names(config, fix, yes)?;
+    check_reasons(config)?;
+    if shared_reasons {
+        check_shared_reasons(config)?;
     }
     Ok(())
 }
 
-fn list_domains() -> io::Result<()> {
-    // Load the domain entries and their reasons from the JSON file
-    let entries = load_reason_log()?;
-    let mut reasons_map = HashMap::new();
-    for entry in entries {
-        reasons_map.insert(entry.domain.clone(), entry.reason);
+// Reports stored domains that the validation new adds go through would reject today, typically
+// entries from before it existed. Unicode names can be re-encoded to punycode, the rest removed.
+fn check_domain_names(config: &Config, fix: bool, yes: bool) -> Result<()> {
+    let mut domains: Vec<String> = read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).collect();
+    domains.extend(load_reason_log(config)?.iter().map(|entry| normalize_domain(&entry.domain)));
+    domains.sort();
+    domains.dedup();
+
+    println!("Checking domain names...");
+    let mut invalid: Vec<(String, Option<String>)> = Vec::new();
+    for domain in domains {
+        let Err(err) = validate_domain(&domain) else { continue };
+        let encoded = idna::domain_to_ascii(&domain).ok().filter(|ascii| *ascii != domain && validate_domain(ascii).is_ok());
+        match &encoded {
+            Some(ascii) => println!(" - {} The fix re-encodes it as {}.", err, ascii),
+            None => println!(" - {} The fix removes it.", err),
+        }
+        invalid.push((domain, encoded));
+    }
+    if invalid.is_empty() {
+        println!("No invalid domain names found.");
+        return Ok(());
+    }
+    if !fix {
+        println!("Run doctor --fix to re-encode or remove them.");
+        return Ok(());
     }
 
-    // Read the zones file and collect domains
-    let file = fs::File::open(ZONES_FILE_PATH)?;
-    let reader = BufReader::new(file);
-    let mut listed_domains = Vec::new();
+    let question = format!("This re-encodes or removes {} {}.", invalid.len(), if invalid.len() == 1 { "domain" } else { "domains" });
+    if !confirm::confirm(&question, yes)? {
+        println!("Nothing was changed.");
+        return Ok(());
+    }
 
-    for line in reader.lines() {
-        let line = line?;
-        if let Some(domain) = parse_domain_from_line(&line) {
-            listed_domains.push(domain);
+    let mut workspace = batch::Workspace::load(config)?;
+    let entries = load_reason_log(config)?;
+    for (domain, encoded) in &invalid {
+        if !workspace.owned_domains().contains(domain) && workspace.domains().contains(domain) {
+            println!("Warning! {} is defined in an included file, fix it there.", domain);
+            continue;
+        }
+        let entry = entries.iter().find(|entry| normalize_domain(&entry.domain) == *domain).cloned();
+        workspace.remove(domain);
+        if let Some(ascii) = encoded {
+            let entry = entry.unwrap_or_else(|| DomainEntry::new(config, domain));
+            workspace.add(DomainEntry { domain: ascii.clone(), ..entry })?;
+            println!("Re-encoded {} as {}.", domain, ascii);
+        } else {
+            println!("Removed {}.", domain);
         }
+        logging::event(Level::Info, "doctor", Some(domain), if encoded.is_some() { "re-encoded" } else { "removed" }, None);
     }
+    workspace.commit()
+}
 
-    // Sort domains alphabetically
-    listed_domains.sort();
+// Reports zone db files that blocked domains point at but that no longer exist, so BIND can't load the zones
+fn check_targets(config: &Config, zone_files: &HashMap<String, String>) -> Result<()> {
+    if config.forwards_listed() || config.output == OutputFormat::Hosts {
+        return Ok(());
+    }
 
-    // Print the domains with reasons
-    println!("Listing {} {}:", listed_domains.len(), if listed_domains.len() == 1 { "domain" } else { "domains" });
-    // add padding to the right of the domain name
-    let max_len = listed_domains.iter().map(|d| d.len()).max().unwrap_or(0);
+    let mut by_target: HashMap<&str, Vec<String>> = HashMap::new();
+    for domain in read_zone_domains(config)? {
+        let domain = normalize_domain(&domain);
+        let target = zone_files.get(&domain).unwrap_or(&config.zone_db);
+        by_target.entry(target).or_default().push(domain);
+    }
+    let mut targets: Vec<(&str, Vec<String>)> = by_target.into_iter().collect();
+    targets.sort();
 
-    for (_i, domain) in listed_domains.iter().enumerate() {
-        // println!(" - {} » {}", domain, reasons_map.get(domain).unwrap_or(&"No reason provided.".to_string()));
-        println!(" - {:<width$} » {}", domain, reasons_map.get(domain).unwrap_or(&"No reason provided.".to_string()), width = max_len);
+    println!("Checking zone db files...");
+    let mut missing = 0;
+    for (target, domains) in &targets {
+        if let Some(problem) = check_zone_db(config, target) {
+            missing += 1;
+            let examples: Vec<&str> = domains.iter().take(5).map(String::as_str).collect();
+            let more = if domains.len() > examples.len() { format!(" and {} more", domains.len() - examples.len()) } else { String::new() };
+            println!(" - {}", problem);
+            println!("   used by {} {}: {}{}", domains.len(), if domains.len() == 1 { "domain" } else { "domains" }, examples.join(", "), more);
+        }
+    }
+    if missing == 0 {
+        let (files, exist) = if targets.len() == 1 { ("file", "exists") } else { ("files", "exist") };
+        println!("All {} zone db {} {}.", targets.len(), files, exist);
     }
+    Ok(())
+}
+
+// Reports reasons that are probably the same one typed inconsistently, and domains with no real reason
+fn check_reasons(config: &Config) -> Result<()> {
+    let entries = load_reason_log(config)?;
+    let mut spellings: HashMap<String, HashMap<&str, usize>> = HashMap::new();
+    for entry in &entries {
+        *spellings.entry(fold_reason(&entry.reason)).or_default().entry(entry.reason.as_str()).or_default() += 1;
+    }
+
+    let mut inconsistent: Vec<Vec<(&str, usize)>> = spellings
+        .into_values()
+        .filter(|variants| variants.len() > 1)
+        .map(|variants| {
+            let mut variants: Vec<(&str, usize)> = variants.into_iter().collect();
+            variants.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            variants
+        })
+        .collect();
+    inconsistent.sort();
 
+    println!("Checking reasons...");
+    for variants in &inconsistent {
+        println!(" - {} spellings of the same reason:", variants.len());
+        for (reason, count) in variants {
+            println!("     \"{}\" ({})", reason, count);
+        }
+        // Relabel matches folded, so one command consolidates every spelling onto the most used one
+        println!("   consolidate with: bind_manager relabel \"{}\" \"{}\"", variants[1].0, variants[0].0);
+    }
+
+    // Zones without a reason log entry show the default reason too
+    let with_entry: HashSet<String> = entries.iter().map(|entry| normalize_domain(&entry.domain)).collect();
+    let mut unexplained: Vec<String> = entries
+        .iter()
+        .filter(|entry| entry.reason == config.default_reason)
+        .map(|entry| normalize_domain(&entry.domain))
+        .chain(read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).filter(|domain| !with_entry.contains(domain)))
+        .collect();
+    unexplained.sort();
+    if !unexplained.is_empty() {
+        let examples: Vec<&str> = unexplained.iter().take(5).map(String::as_str).collect();
+        let more = if unexplained.len() > examples.len() { ", ..." } else { "" };
+        println!(" - {} {} \"{}\": {}{}", unexplained.len(), if unexplained.len() == 1 { "domain has" } else { "domains have" }, config.default_reason, examples.join(", "), more);
+    }
+
+    if inconsistent.is_empty() && unexplained.is_empty() {
+        println!("No reason problems found.");
+    }
     Ok(())
 }
 
-fn parse_domain_from_line(line: &str) -> Option<String> {
-    // A simple parser for the domain in the line. Adjust regex as needed.
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    if let Some(part) = parts.get(1) {
-        if part.starts_with('"') && part.ends_with('"') {
-            return Some(part.trim_matches('"').to_string());
+// A reason given to at least this many unrelated registrable domains looks like a script or
+// copy-paste mistake rather than one campaign
+const SHARED_REASON_MIN_DOMAINS: usize = 50;
+
+// Warns about reasons shared by many domains with no registrable domain in common, e.g. a whole
+// import tagged with one incident by mistake. Only reports, nothing is changed.
+fn check_shared_reasons(config: &Config) -> Result<()> {
+    let mut by_reason: HashMap<&str, BTreeMap<String, Vec<String>>> = HashMap::new();
+    let entries = load_reason_log(config)?;
+    for entry in entries.iter().filter(|entry| entry.reason != config.default_reason) {
+        let domain = normalize_domain(&entry.domain);
+        let registrable = psl::domain_str(&domain).unwrap_or(&domain).to_string();
+        by_reason.entry(entry.reason.as_str()).or_default().entry(registrable).or_default().push(domain);
+    }
+
+    let mut shared: Vec<(&str, BTreeMap<String, Vec<String>>)> =
+        by_reason.into_iter().filter(|(_, registrable)| registrable.len() >= SHARED_REASON_MIN_DOMAINS).collect();
+    shared.sort_by(|a, b| b.1.len().cmp(&a.1.len()).then(a.0.cmp(b.0)));
+
+    println!("Checking for shared reasons...");
+    for (reason, registrable) in &shared {
+        let count: usize = registrable.values().map(Vec::len).sum();
+        let sample: Vec<&str> = registrable.values().filter_map(|domains| domains.first()).take(5).map(String::as_str).collect();
+        println!(
+            " - \"{}\" is the reason for {} domains across {} unrelated registrable domains, possibly by mistake: {}, ...",
+            reason,
+            count,
+            registrable.len(),
+            sample.join(", ")
+        );
+    }
+    if shared.is_empty() {
+        println!("No suspiciously shared reasons found.");
+    }
+    Ok(())
+}
+
+// Every zone db file the zones file can point at: the configured defaults plus per-domain overrides
+fn zone_db_targets(config: &Config, entries: &[DomainEntry]) -> Vec<String> {
+    let mut targets = vec![config.zone_db.clone()];
+    targets.extend(config.log_zone_db.clone());
+    for zone_file in entries.iter().filter_map(|entry| entry.zone_file.as_ref()) {
+        if !targets.contains(zone_file) {
+            targets.push(zone_file.clone());
+        }
+    }
+    targets
+}
+
+// A fast pass/fail check for container liveness and readiness probes: the files are readable and
+// parse cleanly, and the reload command is there. Nothing is written and no process is started,
+// and the only output is OK or the first problem.
+fn healthcheck(config: &Config) -> Result<()> {
+    let unhealthy = |problem: String| Err(Error::Config(format!("Unhealthy: {}", problem)));
+
+    for source in zones::read_sources(config.backend.as_ref(), &config.zones_file)? {
+        if let Some((line, problem)) = zones::junk_lines(&source.contents).into_iter().next() {
+            return unhealthy(format!("{}:{}: {}", source.path.display(), line, problem));
         }
     }
-    None
-}
\ No newline at end of file
+    if let Err(err) = load_reason_log(config) {
+        return unhealthy(err.to_string());
+    }
+
+    let program = config.reload_command.split_whitespace().next().unwrap_or_default();
+    if !config.no_reload && !on_path(program) {
+        return unhealthy(format!("the reload command {} is not on PATH", if program.is_empty() { "(empty)" } else { program }));
+    }
+    out!("OK");
+    Ok(())
+}
+
+// Whether a program can be run, looking it up on PATH like the shell unless it is a path itself
+fn on_path(program: &str) -> bool {
+    let is_executable = |path: &Path| {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        }
+        #[cfg(not(unix))]
+        {
+            path.is_file()
+        }
+    };
+    if program.is_empty() {
+        return false;
+    }
+    if program.contains('/') {
+        return is_executable(Path::new(program));
+    }
+    std::env::var_os("PATH").is_some_and(|paths| std::env::split_paths(&paths).any(|dir| is_executable(&dir.join(program))))
+}
+
+// Checks the setup end to end without changing anything
+fn selftest(config: &Config) -> Result<()> {
+    let mut failures = 0;
+    let mut report = |name: &str, problem: Option<String>| match problem {
+        None => println!("[ OK ] {}", name),
+        Some(problem) => {
+            failures += 1;
+            println!("[FAIL] {}: {}", name, problem);
+        }
+    };
+
+    report("zones file", read_zone_domains(config).err().map(|err| err.to_string()));
+    let entries = load_reason_log(config);
+    report("reason log", entries.as_ref().err().map(|err| err.to_string()));
+    for target in zone_db_targets(config, entries.as_deref().unwrap_or_default()) {
+        report(&format!("zone db {}", target), check_zone_db(config, &target));
+    }
+
+    // A missing reload command only means changes aren't reloaded, which reload_bind already tolerates
+    let program = config.reload_command.split_whitespace().next().unwrap_or_default();
+    if config.no_reload {
+        println!("[ OK ] reload: disabled with no_reload");
+    } else if on_path(program) {
+        println!("[ OK ] reload command {}", program);
+    } else {
+        println!("[WARN] reload command: {} not found on PATH, changes will not be reloaded.", program);
+    }
+
+    if failures == 0 {
+        println!("All checks passed.");
+        Ok(())
+    } else {
+        Err(Error::Config(format!("{} self-test {} failed.", failures, if failures == 1 { "check" } else { "checks" })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The display column the reason starts at in a row of `list`
+    fn reason_column(row: &str) -> usize {
+        row.split(" | ").next().unwrap_or_default().width() + 3
+    }
+
+    #[test]
+    fn pad_aligns_wide_characters() {
+        let domains = ["example.com", "例え.jp", "ｆｕｌｌ.example", "bücher.de"];
+        let width = domains.iter().map(|domain| domain.width()).max().unwrap_or(0);
+        let rows: Vec<String> = domains.iter().map(|domain| format!("{} | {}", pad(domain, width), "reason")).collect();
+
+        for row in &rows {
+            assert_eq!(reason_column(row), width + 3, "{}", row);
+            assert_eq!(row.width(), rows[0].width());
+        }
+        // Two columns each, so padding by bytes or chars would have come out short
+        assert_eq!(pad("例え.jp", 9), "例え.jp  ");
+    }
+
+    #[test]
+    fn truncate_counts_display_columns() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("phishing kit", 8), "phishin…");
+        // A wide character that would straddle the limit is left out rather than overflowing it
+        assert_eq!(truncate("フィッシング詐欺", 6), "フィ…");
+        assert_eq!(truncate("フィッシング詐欺", 7), "フィッ…");
+        for width in 1..12 {
+            assert!(truncate("フィッシング詐欺 phishing", width).width() <= width, "width {}", width);
+        }
+    }
+}
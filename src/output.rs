@@ -0,0 +1,84 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// With --output, the primary result of a command (listings, stats, JSON) is written to a file
+// instead of stdout, and the warnings that come with it go to stderr so they can't corrupt it.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use bind_manager::error::{Error, Result};
+
+struct Redirect {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    // The first write that failed, reported once the command is done
+    error: Option<io::Error>,
+}
+
+static REDIRECT: Mutex<Option<Redirect>> = Mutex::new(None);
+
+fn redirect() -> MutexGuard<'static, Option<Redirect>> {
+    REDIRECT.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// println! for the primary result of a command
+macro_rules! out {
+    () => {
+        $crate::output::line(format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::output::line(format_args!($($arg)*))
+    };
+}
+
+// println! for the warnings that come with a result, on stderr when the result goes to a file
+macro_rules! note {
+    ($($arg:tt)*) => {
+        $crate::output::note(format_args!($($arg)*))
+    };
+}
+
+pub fn to_file(path: &Path) -> Result<()> {
+    let file = File::create(path).map_err(Error::writing(path))?;
+    *redirect() = Some(Redirect { path: path.to_path_buf(), writer: BufWriter::new(file), error: None });
+    Ok(())
+}
+
+pub fn line(args: fmt::Arguments) {
+    match redirect().as_mut() {
+        Some(redirect) => {
+            if let (Err(err), None) = (writeln!(redirect.writer, "{}", args), &redirect.error) {
+                redirect.error = Some(err);
+            }
+        }
+        None => println!("{}", args),
+    }
+}
+
+pub fn is_redirected() -> bool {
+    redirect().is_some()
+}
+
+pub fn note(args: fmt::Arguments) {
+    match is_redirected() {
+        true => eprintln!("{}", args),
+        false => println!("{}", args),
+    }
+}
+
+// Flushes the output file, failing if anything could not be written to it
+pub fn finish() -> Result<()> {
+    let Some(mut redirect) = redirect().take() else {
+        return Ok(());
+    };
+    match redirect.error.take() {
+        Some(err) => Err(Error::writing(&redirect.path)(err)),
+        None => redirect.writer.flush().map_err(Error::writing(&redirect.path)),
+    }
+}
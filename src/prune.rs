@@ -0,0 +1,91 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::HashSet;
+use chrono::{DateTime, Duration, Months, Utc};
+
+use bind_manager::config::Config;
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::{load_reason_log, normalize_domain, read_zone_domains, remove_domain_set, DomainEntry};
+
+use crate::{display_reason, reload_bind, report_count_delta, summary};
+
+pub struct PruneFilter {
+    pub tag: Option<String>,
+    pub reason: Option<String>,
+    pub category: Option<String>,
+}
+
+impl PruneFilter {
+    fn matches(&self, entry: &DomainEntry) -> bool {
+        self.tag.as_ref().is_none_or(|tag| entry.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            && self.reason.as_ref().is_none_or(|reason| entry.reason.to_lowercase().contains(&reason.to_lowercase()))
+            && self.category.as_ref().is_none_or(|category| entry.category.as_deref().is_some_and(|c| c.eq_ignore_ascii_case(category)))
+    }
+}
+
+// The time `age` (e.g. 90d, 12w, 6m or 1y) before now
+fn cutoff(age: &str) -> Result<DateTime<Utc>> {
+    let invalid = || Error::InvalidArgument(format!("Invalid age {}, expected a number of days, weeks, months or years such as 90d, 12w, 6m or 1y.", age));
+    let age = age.trim();
+    let (count, unit) = age.split_at(age.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?);
+    let count: u32 = count.parse().map_err(|_| invalid())?;
+    let now = Utc::now();
+    let cutoff = match unit {
+        "d" => now.checked_sub_signed(Duration::days(count as i64)),
+        "w" => now.checked_sub_signed(Duration::weeks(count as i64)),
+        "m" => now.checked_sub_months(Months::new(count)),
+        "y" => count.checked_mul(12).and_then(|months| now.checked_sub_months(Months::new(months))),
+        _ => None,
+    };
+    cutoff.ok_or_else(invalid)
+}
+
+// Lists the blocks added before the cutoff that match the filter, and removes them with yes.
+// Entries without a recorded date can't be aged and are left alone.
+pub fn run(config: &Config, older_than: &str, filter: &PruneFilter, yes: bool) -> Result<()> {
+    let cutoff = cutoff(older_than)?;
+    let blocked: HashSet<String> = read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).collect();
+    let mut candidates: Vec<DomainEntry> =
+        load_reason_log(config)?.into_iter().filter(|entry| entry.active && blocked.contains(&normalize_domain(&entry.domain)) && filter.matches(entry)).collect();
+    let undated = candidates.iter().filter(|entry| entry.added_at.is_none()).count();
+    candidates.retain(|entry| entry.added_at.is_some_and(|added| added < cutoff));
+    candidates.sort_by(|a, b| a.added_at.cmp(&b.added_at).then(a.domain.cmp(&b.domain)));
+
+    let since = cutoff.format("%Y-%m-%d");
+    if undated > 0 {
+        println!("Skipping {} matching {} without a recorded date.", undated, if undated == 1 { "domain" } else { "domains" });
+    }
+    if candidates.is_empty() {
+        println!("No matching domains were added before {}.", since);
+        return Ok(());
+    }
+
+    let count = format!("{} {}", candidates.len(), if candidates.len() == 1 { "domain" } else { "domains" });
+    println!("{} {} added before {}:", if yes { "Pruning" } else { "Would prune" }, count, since);
+    for entry in &candidates {
+        let added = entry.added_at.map(|added| added.format("%Y-%m-%d").to_string()).unwrap_or_default();
+        println!(" - {} (added {}) » {}", normalize_domain(&entry.domain), added, display_reason(config, entry));
+    }
+    if !yes {
+        println!("Nothing was removed, run again with --yes to prune them.");
+        return Ok(());
+    }
+
+    let before = read_zone_domains(config)?.len();
+    let removed = remove_domain_set(config, &candidates.iter().map(|entry| normalize_domain(&entry.domain)).collect())?;
+    summary::removed(removed.len());
+    for domain in &removed {
+        logging::event(Level::Info, "prune", Some(domain), "removed", Some(&format!("added before {}", since)));
+    }
+    println!("Pruned {} {} added before {}.", removed.len(), if removed.len() == 1 { "domain" } else { "domains" }, since);
+    report_count_delta(config, before)?;
+    // Once for the whole prune
+    if !removed.is_empty() {
+        reload_bind(config)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// Reading and writing the reason log in each of the supported formats
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, ReasonLogFormat, CONFIG_PATH};
+use crate::error::{Error, Result};
+use crate::DomainEntry;
+
+// TOML needs a table at the top, so the entries are kept as an array of [[domain]] tables
+#[derive(Serialize, Deserialize)]
+struct TomlLog {
+    #[serde(default)]
+    domain: Vec<DomainEntry>,
+}
+
+impl ReasonLogFormat {
+    // The format named on the command line, None for one that isn't supported
+    pub fn parse(name: &str) -> Option<ReasonLogFormat> {
+        match name {
+            "json" => Some(ReasonLogFormat::Json),
+            "jsonl" => Some(ReasonLogFormat::Jsonl),
+            "toml" => Some(ReasonLogFormat::Toml),
+            _ => None,
+        }
+    }
+
+    // Recognises the format of existing contents, None when there is nothing to go by
+    pub fn detect(contents: &[u8]) -> Option<ReasonLogFormat> {
+        let text = String::from_utf8_lossy(contents);
+        let text = text.trim_start();
+        if text.is_empty() {
+            return None;
+        }
+        if text.starts_with('{') {
+            return Some(ReasonLogFormat::Jsonl);
+        }
+        // A JSON array of entries, as opposed to a TOML [[domain]] table or key
+        match text.strip_prefix('[').map(str::trim_start) {
+            Some(rest) if rest.starts_with('{') || rest.starts_with(']') => Some(ReasonLogFormat::Json),
+            _ => Some(ReasonLogFormat::Toml),
+        }
+    }
+
+    pub fn decode(self, contents: &[u8]) -> Result<Vec<DomainEntry>> {
+        match self {
+            ReasonLogFormat::Json => Ok(serde_json::from_slice(contents)?),
+            ReasonLogFormat::Jsonl => String::from_utf8_lossy(contents)
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| Ok(serde_json::from_str(line)?))
+                .collect(),
+            ReasonLogFormat::Toml => {
+                let log: TomlLog = toml::from_str(&String::from_utf8_lossy(contents))
+                    .map_err(|err| Error::Config(format!("Invalid TOML reason log: {}", err)))?;
+                Ok(log.domain)
+            }
+        }
+    }
+
+    pub fn encode(self, entries: &[DomainEntry]) -> Result<Vec<u8>> {
+        match self {
+            ReasonLogFormat::Json => Ok(serde_json::to_vec(entries)?),
+            ReasonLogFormat::Jsonl => {
+                let mut contents = Vec::new();
+                for entry in entries {
+                    serde_json::to_writer(&mut contents, entry)?;
+                    contents.push(b'\n');
+                }
+                Ok(contents)
+            }
+            ReasonLogFormat::Toml => toml::to_string(&TomlLog { domain: entries.to_vec() })
+                .map(String::into_bytes)
+                .map_err(|err| Error::Config(format!("Cannot write the reason log as TOML: {}", err))),
+        }
+    }
+}
+
+// The declared format, or else the one the current file is in, JSON for a new file. A file in a
+// different format than the declared one is an error: decoding it as the declared one would fail,
+// and saving over it would lose every entry.
+pub fn format_of(config: &Config, contents: Option<&[u8]>) -> Result<ReasonLogFormat> {
+    let detected = contents.and_then(ReasonLogFormat::detect);
+    match (config.reason_log_format, detected) {
+        (Some(declared), Some(detected)) if declared != detected => Err(Error::Config(format!(
+            "reason_log_format in {} is {}, but {} is {}. Run convert-reason-log {} to convert it, or set reason_log_format to {}.",
+            CONFIG_PATH,
+            declared.as_str(),
+            config.reason_log.display(),
+            detected.as_str(),
+            declared.as_str(),
+            detected.as_str()
+        ))),
+        (declared, detected) => Ok(declared.or(detected).unwrap_or_default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<DomainEntry> {
+        vec![
+            DomainEntry { domain: "example.com".to_string(), reason: "phishing \"kit\"".to_string(), active: true, ..Default::default() },
+            DomainEntry { domain: "zip".to_string(), reason: "abused tld".to_string(), tags: vec!["tld".to_string()], suffix: true, active: true, ..Default::default() },
+            DomainEntry { domain: "staged.example".to_string(), reason: "pending".to_string(), active: false, ..Default::default() },
+        ]
+    }
+
+    fn same(a: &[DomainEntry], b: &[DomainEntry]) -> bool {
+        serde_json::to_string(a).unwrap() == serde_json::to_string(b).unwrap()
+    }
+
+    #[test]
+    fn every_format_is_detected_and_round_trips() {
+        for format in [ReasonLogFormat::Json, ReasonLogFormat::Jsonl, ReasonLogFormat::Toml] {
+            let encoded = format.encode(&entries()).unwrap();
+            assert_eq!(ReasonLogFormat::detect(&encoded), Some(format));
+            assert!(same(&format.decode(&encoded).unwrap(), &entries()), "{}", format.as_str());
+        }
+    }
+
+    #[test]
+    fn jsonl_and_toml_convert_both_ways() {
+        let jsonl = ReasonLogFormat::Jsonl.encode(&entries()).unwrap();
+        let toml = ReasonLogFormat::Toml.encode(&ReasonLogFormat::Jsonl.decode(&jsonl).unwrap()).unwrap();
+        let back = ReasonLogFormat::Jsonl.encode(&ReasonLogFormat::Toml.decode(&toml).unwrap()).unwrap();
+        assert_eq!(back, jsonl);
+    }
+
+    #[test]
+    fn a_declared_format_the_file_is_not_in_is_refused() {
+        let config = Config { reason_log_format: Some(ReasonLogFormat::Toml), ..Config::default() };
+        let json = ReasonLogFormat::Json.encode(&entries()).unwrap();
+        match format_of(&config, Some(&json)) {
+            Err(Error::Config(message)) => assert!(message.contains("convert-reason-log toml"), "{}", message),
+            other => panic!("expected a config error, got {:?}", other.map(|format| format.as_str())),
+        }
+
+        // Nothing to go by, or the same format, is fine
+        assert_eq!(format_of(&config, None).unwrap(), ReasonLogFormat::Toml);
+        assert_eq!(format_of(&config, Some(b"  ")).unwrap(), ReasonLogFormat::Toml);
+        assert_eq!(format_of(&config, Some(&ReasonLogFormat::Toml.encode(&entries()).unwrap())).unwrap(), ReasonLogFormat::Toml);
+        assert_eq!(format_of(&Config::default(), Some(&json)).unwrap(), ReasonLogFormat::Json);
+    }
+
+    #[test]
+    fn format_names_are_parsed_strictly() {
+        assert_eq!(ReasonLogFormat::parse("jsonl"), Some(ReasonLogFormat::Jsonl));
+        assert_eq!(ReasonLogFormat::parse("yaml"), None);
+    }
+}
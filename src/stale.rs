@@ -0,0 +1,114 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use chrono::{NaiveDate, NaiveDateTime};
+
+use bind_manager::config::Config;
+use bind_manager::error::{Error, Result};
+use bind_manager::logging::{self, Level};
+use bind_manager::{load_reason_log, normalize_domain, read_zone_domains, remove_domain_set};
+
+use crate::{confirm, reload_bind, report_count_delta, summary};
+
+// A BIND querylog line looks like
+//   14-Oct-2026 12:00:00.123 queries: info: client @0x7f.. 10.0.0.2#5353 (example.com): query: example.com IN A +E(0)K (10.0.0.1)
+// with the timestamp and category prefix depending on the logging channel options. Returns the
+// queried name and, when the line starts with one, the timestamp.
+fn parse_query_line(line: &str) -> Option<(String, Option<NaiveDateTime>)> {
+    let (_, query) = line.split_once(" query: ")?;
+    let name = query.split_whitespace().next()?;
+
+    let mut words = line.split_whitespace();
+    let time = match (words.next(), words.next()) {
+        (Some(date), Some(time)) => NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%d-%b-%Y %H:%M:%S%.f").ok(),
+        _ => None,
+    };
+    Some((normalize_domain(name), time))
+}
+
+// Every blocked domain that has a query at or under it on or after `since`
+fn queried_domains(path: &Path, blocked: &HashSet<String>, since: NaiveDate) -> Result<HashSet<String>> {
+    let file = File::open(path).map_err(Error::reading(path))?;
+    let mut seen = HashSet::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(Error::reading(path))?;
+        let (name, time) = match parse_query_line(&line) {
+            Some(query) => query,
+            None => continue,
+        };
+        // Lines without a timestamp can't be placed in the window, so count them as recent
+        if time.is_some_and(|time| time.date() < since) {
+            continue;
+        }
+
+        // A zone answers for all of its subdomains, so a query for a.b.example.com hits example.com
+        let mut suffix = name.as_str();
+        loop {
+            if blocked.contains(suffix) {
+                seen.insert(suffix.to_string());
+            }
+            match suffix.split_once('.') {
+                Some((_, rest)) => suffix = rest,
+                None => break,
+            }
+        }
+    }
+    Ok(seen)
+}
+
+// Lists blocked domains nobody has queried since the given date, and removes them with prune once
+// confirmed (or with yes)
+pub fn run(config: &Config, query_log: &Path, since: &str, prune: bool, yes: bool) -> Result<()> {
+    let since = NaiveDate::parse_from_str(since, "%Y-%m-%d")
+        .map_err(|_| Error::InvalidArgument(format!("Invalid date {}, expected YYYY-MM-DD.", since)))?;
+
+    let blocked: HashSet<String> = read_zone_domains(config)?.iter().map(|domain| normalize_domain(domain)).collect();
+    let seen = queried_domains(query_log, &blocked, since)?;
+
+    // Domains added after the window started haven't had the whole window to show up
+    let recently_added: HashSet<String> = load_reason_log(config)?
+        .into_iter()
+        .filter(|entry| entry.added_at.is_some_and(|added| added.date_naive() > since))
+        .map(|entry| normalize_domain(&entry.domain))
+        .collect();
+
+    let mut stale: Vec<&String> = blocked.iter().filter(|domain| !seen.contains(*domain) && !recently_added.contains(*domain)).collect();
+    stale.sort();
+
+    if stale.is_empty() {
+        println!("Every blocked domain was queried since {}.", since);
+        return Ok(());
+    }
+
+    println!("{} blocked {} not queried since {}:", stale.len(), if stale.len() == 1 { "domain was" } else { "domains were" }, since);
+    for domain in &stale {
+        println!(" - {}", domain);
+    }
+    if !prune {
+        return Ok(());
+    }
+    let stale: Vec<String> = stale.into_iter().cloned().collect();
+    if !confirm::confirm_removal(&stale, yes)? {
+        println!("Nothing was removed.");
+        return Ok(());
+    }
+
+    let before = read_zone_domains(config)?.len();
+    let removed = remove_domain_set(config, &stale.into_iter().collect())?;
+    summary::removed(removed.len());
+    for domain in &removed {
+        logging::event(Level::Info, "stale", Some(domain), "removed", None);
+    }
+    println!("Removed {} stale {}.", removed.len(), if removed.len() == 1 { "domain" } else { "domains" });
+    report_count_delta(config, before)?;
+    if !removed.is_empty() {
+        reload_bind(config)?;
+    }
+    Ok(())
+}
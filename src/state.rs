@@ -0,0 +1,126 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+
+pub const STATE_PATH: &str = "/etc/bind/bind_manager.state.json";
+
+// Small bits of bookkeeping that outlive a single invocation
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct StateFile {
+    // By Config::reload_key
+    pub reloads: BTreeMap<String, ReloadState>,
+    // How far a chunked import got, so an interrupted one can be resumed
+    pub import_progress: Option<ImportProgress>,
+    // The last `list --index`, which `del-index` picks domains from
+    pub last_listing: Option<ListingRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ListingRecord {
+    pub zones_file: PathBuf,
+    pub category: Option<String>,
+    // A hash of the listed domains in order, to refuse picking by index once the list has changed
+    pub fingerprint: String,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImportProgress {
+    pub path: PathBuf,
+    // The size of the file when the import started, to notice it being replaced in between
+    pub size: u64,
+    // The last line of the file that was imported
+    pub line: usize,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct ReloadState {
+    pub last_reload: Option<ReloadRecord>,
+    pub last_successful_reload: Option<DateTime<Utc>>,
+    // Set when a reload was skipped by the debounce window and nothing has reloaded since
+    pub reload_pending: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReloadRecord {
+    pub at: DateTime<Utc>,
+    pub success: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl StateFile {
+    // Kept in config.backend like the files it is about
+    pub fn load(config: &Config) -> StateFile {
+        // A missing or unreadable state file only means we have nothing recorded yet
+        config
+            .backend
+            .read(Path::new(STATE_PATH))
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, config: &Config) -> io::Result<()> {
+        let contents = serde_json::to_vec_pretty(self)?;
+        config.backend.write(Path::new(STATE_PATH), &contents)
+    }
+
+    pub fn reloads(&self, key: &str) -> ReloadState {
+        self.reloads.get(key).cloned().unwrap_or_default()
+    }
+
+    pub fn record_reload(config: &Config, success: bool, message: Option<String>) {
+        let mut state = StateFile::load(config);
+        let now = Utc::now();
+        let reloads = state.reloads.entry(config.reload_key()).or_default();
+        reloads.last_reload = Some(ReloadRecord { at: now, success, message });
+        if success {
+            reloads.last_successful_reload = Some(now);
+            reloads.reload_pending = false;
+        }
+
+        if let Err(err) = state.save(config) {
+            println!("Warning! Could not record the reload in {}: {}", STATE_PATH, err);
+        }
+    }
+
+    pub fn record_import_progress(config: &Config, progress: Option<ImportProgress>) {
+        let mut state = StateFile::load(config);
+        state.import_progress = progress;
+
+        if let Err(err) = state.save(config) {
+            println!("Warning! Could not record the import progress in {}: {}", STATE_PATH, err);
+        }
+    }
+
+    pub fn record_listing(config: &Config, listing: ListingRecord) {
+        let mut state = StateFile::load(config);
+        state.last_listing = Some(listing);
+
+        if let Err(err) = state.save(config) {
+            println!("Warning! Could not record the listing in {}: {}", STATE_PATH, err);
+        }
+    }
+
+    pub fn mark_reload_pending(config: &Config) {
+        let mut state = StateFile::load(config);
+        state.reloads.entry(config.reload_key()).or_default().reload_pending = true;
+
+        if let Err(err) = state.save(config) {
+            println!("Warning! Could not record the pending reload in {}: {}", STATE_PATH, err);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Tallies of what a command changed, for the `--summary` line scripts can parse
+static ADDED: AtomicUsize = AtomicUsize::new(0);
+static UPDATED: AtomicUsize = AtomicUsize::new(0);
+static REMOVED: AtomicUsize = AtomicUsize::new(0);
+static RELOADED: AtomicBool = AtomicBool::new(false);
+
+pub fn added(count: usize) {
+    ADDED.fetch_add(count, Ordering::Relaxed);
+}
+
+pub fn updated(count: usize) {
+    UPDATED.fetch_add(count, Ordering::Relaxed);
+}
+
+pub fn removed(count: usize) {
+    REMOVED.fetch_add(count, Ordering::Relaxed);
+}
+
+pub fn reloaded() {
+    RELOADED.store(true, Ordering::Relaxed);
+}
+
+pub fn was_reloaded() -> bool {
+    RELOADED.load(Ordering::Relaxed)
+}
+
+// `CHANGED added=3 updated=1 removed=0 reloaded=yes`, the same shape for every command
+pub fn print() {
+    println!(
+        "CHANGED added={} updated={} removed={} reloaded={}",
+        ADDED.load(Ordering::Relaxed),
+        UPDATED.load(Ordering::Relaxed),
+        REMOVED.load(Ordering::Relaxed),
+        if was_reloaded() { "yes" } else { "no" }
+    );
+}
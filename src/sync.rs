@@ -0,0 +1,117 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::collections::HashSet;
+
+use bind_manager::config::Config;
+use bind_manager::error::Result;
+use bind_manager::logging::{self, Level};
+use bind_manager::zones::parse_domain_from_line;
+use bind_manager::{ensure_header, load_reason_log, normalize_domain, read_zone_domains, read_zones_file, save_reason_log, validate_domain, write_zones_file, DomainEntry};
+
+use crate::reload_bind;
+
+// Rebuilds the reason log from the zones file so it has exactly one normalized entry per zone,
+// keeping the reasons already recorded. With rewrite, zone names in the zones file itself are
+// normalized too, so both files agree.
+pub fn run(config: &Config, rewrite: bool) -> Result<()> {
+    let mut canonicalized: Vec<(String, String)> = Vec::new();
+    let mut note_canonical = |raw: &str, domain: &str| {
+        if raw != domain && !canonicalized.iter().any(|(seen, _)| seen == raw) {
+            canonicalized.push((raw.to_string(), domain.to_string()));
+        }
+    };
+
+    let mut zone_domains = Vec::new();
+    let mut seen = HashSet::new();
+    let mut zones_canonical = true;
+    for raw in read_zone_domains(config)? {
+        let domain = normalize_domain(&raw);
+        if let Err(err) = validate_domain(&domain) {
+            eprintln!("Warning: zone {} is not a valid domain ({}), leaving it out of the reason log.", raw, err);
+            continue;
+        }
+        zones_canonical &= raw == domain;
+        note_canonical(&raw, &domain);
+        if seen.insert(domain.clone()) {
+            zone_domains.push(domain);
+        }
+    }
+
+    let mut old_entries = load_reason_log(config)?;
+    for entry in &old_entries {
+        note_canonical(&entry.domain, &normalize_domain(&entry.domain));
+    }
+
+    let mut added = 0;
+    let mut entries = Vec::with_capacity(zone_domains.len());
+    for domain in &zone_domains {
+        // The first recorded spelling wins if the log had several for the same domain
+        match old_entries.iter().position(|entry| normalize_domain(&entry.domain) == *domain) {
+            // A staged domain whose zone was written by hand is active now
+            Some(index) => entries.push(DomainEntry { domain: domain.clone(), active: true, ..old_entries.swap_remove(index) }),
+            None => {
+                entries.push(DomainEntry::new(config, domain));
+                added += 1;
+            }
+        }
+        old_entries.retain(|entry| normalize_domain(&entry.domain) != *domain);
+    }
+    // Staged entries have no zone yet and are kept
+    entries.extend(old_entries.iter().filter(|entry| !entry.active).cloned());
+    old_entries.retain(|entry| entry.active);
+    let dropped = old_entries.len();
+    let entries_written = save_reason_log(config, &entries)?;
+
+    let rewritten = if rewrite { rewrite_zone_names(config)? } else { 0 };
+
+    for (raw, domain) in &canonicalized {
+        println!("Canonicalized {} to {}", raw, domain);
+    }
+    println!(
+        "Synced the reason log: {} entries, {} added for unrecorded zones, {} dropped without a zone, {} canonicalized.",
+        entries.len(),
+        added,
+        dropped,
+        canonicalized.len()
+    );
+    if !rewrite && !zones_canonical {
+        println!("The zones file still has non-canonical names, run sync --rewrite to normalize them as well.");
+    }
+    logging::event(Level::Info, "sync", None, "synced", Some(&format!("{} entries, {} canonicalized", entries.len(), canonicalized.len())));
+
+    if rewritten > 0 {
+        println!("Rewrote {} zone names in {}.", rewritten, config.zones_file.display());
+        reload_bind(config)?;
+    } else if !entries_written {
+        println!("Already up to date, nothing was written.");
+    }
+    Ok(())
+}
+
+// Normalizes the zone names in the zones file itself (included files are left alone), returning how many changed
+fn rewrite_zone_names(config: &Config) -> Result<usize> {
+    let contents = read_zones_file(config)?;
+
+    let mut rewritten = 0;
+    let mut canonical = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        match parse_domain_from_line(line) {
+            Some(raw) if raw != "." && normalize_domain(&raw) != raw => {
+                canonical.push_str(&line.replacen(&format!("\"{}\"", raw), &format!("\"{}\"", normalize_domain(&raw)), 1));
+                rewritten += 1;
+            }
+            _ => canonical.push_str(line),
+        }
+        canonical.push('\n');
+    }
+
+    if rewritten > 0 {
+        let mut canonical = canonical.into_bytes();
+        ensure_header(config, &mut canonical);
+        write_zones_file(config, &canonical)?;
+    }
+    Ok(rewritten)
+}
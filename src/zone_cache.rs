@@ -0,0 +1,221 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+// A prebuilt copy of the parsed zone set, so frequent lookups (`check` from monitoring) don't
+// parse the whole zones file every time. It records the modification time of the zones file and
+// of each file it includes, and is rebuilt as soon as any of them differs.
+//
+// The format is compact and private to bind_manager: a magic, the number of source files, each as
+// its path, mtime seconds and nanoseconds, then the number of domains and each normalized domain,
+// every string prefixed with its length. Anything unexpected just means the cache is rebuilt.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::{debug, normalize_domain, zone_domains_in, zones};
+
+const MAGIC: &[u8] = b"BMZC\x01";
+
+struct Stamp {
+    path: PathBuf,
+    modified: Duration,
+}
+
+struct Cache {
+    stamps: Vec<Stamp>,
+    domains: HashSet<String>,
+}
+
+// The normalized zone domains, from the cache when it is still fresh. Without a configured
+// zone_cache, or a backend that can't tell modification times, this is a plain parse.
+pub fn zone_set(config: &Config) -> Result<HashSet<String>> {
+    let Some(cache_path) = &config.zone_cache else {
+        return parse(config);
+    };
+
+    let cache = config.backend.read(cache_path).ok().and_then(|contents| decode(&contents));
+    match cache {
+        Some(cache) if is_fresh(config, &cache) => {
+            debug::log(format_args!("using the zone cache {}", cache_path.display()));
+            return Ok(cache.domains);
+        }
+        Some(_) => debug::log("the zone cache is stale, rebuilding it"),
+        None => debug::log("no usable zone cache, building it"),
+    }
+
+    let started = SystemTime::now();
+    let sources = zones::read_sources(config.backend.as_ref(), &config.zones_file)?;
+    let domains: HashSet<String> = zone_domains_in(config, &sources).iter().map(|domain| normalize_domain(domain)).collect();
+    let stamps: Option<Vec<Stamp>> = sources.iter().map(|source| stamp(config, &source.path)).collect();
+    // A file changed while it was being read might not be in this parse, so it isn't cached
+    match stamps {
+        Some(stamps) if stamps.iter().all(|stamp| UNIX_EPOCH + stamp.modified < started) => {
+            let cache = Cache { stamps, domains };
+            if let Err(err) = config.backend.write(cache_path, &encode(&cache)) {
+                debug::log(format_args!("could not write the zone cache {}: {}", cache_path.display(), err));
+            }
+            Ok(cache.domains)
+        }
+        _ => Ok(domains),
+    }
+}
+
+fn parse(config: &Config) -> Result<HashSet<String>> {
+    let sources = zones::read_sources(config.backend.as_ref(), &config.zones_file)?;
+    Ok(zone_domains_in(config, &sources).iter().map(|domain| normalize_domain(domain)).collect())
+}
+
+fn stamp(config: &Config, path: &Path) -> Option<Stamp> {
+    let modified = config.backend.modified(path).ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(Stamp { path: path.to_path_buf(), modified })
+}
+
+// Fresh if it was built from the configured zones file and none of its files changed since
+fn is_fresh(config: &Config, cache: &Cache) -> bool {
+    cache.stamps.first().is_some_and(|stamp| stamp.path == config.zones_file)
+        && cache.stamps.iter().all(|cached| stamp(config, &cached.path).is_some_and(|current| current.modified == cached.modified))
+}
+
+fn encode(cache: &Cache) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.extend((cache.stamps.len() as u32).to_le_bytes());
+    for stamp in &cache.stamps {
+        put_str(&mut out, &stamp.path.to_string_lossy());
+        out.extend(stamp.modified.as_secs().to_le_bytes());
+        out.extend(stamp.modified.subsec_nanos().to_le_bytes());
+    }
+    out.extend((cache.domains.len() as u32).to_le_bytes());
+    for domain in &cache.domains {
+        put_str(&mut out, domain);
+    }
+    out
+}
+
+fn put_str(out: &mut Vec<u8>, text: &str) {
+    out.extend((text.len() as u32).to_le_bytes());
+    out.extend(text.as_bytes());
+}
+
+fn decode(contents: &[u8]) -> Option<Cache> {
+    let mut reader = Reader { rest: contents.strip_prefix(MAGIC)? };
+    let stamps = (0..reader.u32()?)
+        .map(|_| {
+            let path = PathBuf::from(reader.str()?);
+            let modified = Duration::new(reader.u64()?, reader.u32()?);
+            Some(Stamp { path, modified })
+        })
+        .collect::<Option<Vec<Stamp>>>()?;
+    let domains = (0..reader.u32()?).map(|_| reader.str()).collect::<Option<HashSet<String>>>()?;
+    reader.rest.is_empty().then_some(Cache { stamps, domains })
+}
+
+struct Reader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.rest.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use crate::backend::{FileBackend, InMemory};
+
+    // Memory with modification times set by hand, so a change can be dated after the cache
+    #[derive(Debug, Default)]
+    struct Dated {
+        files: InMemory,
+        modified: Mutex<HashMap<PathBuf, SystemTime>>,
+    }
+
+    impl Dated {
+        fn set(&self, path: &Path, contents: &str, seconds: u64) {
+            self.files.write(path, contents.as_bytes()).unwrap();
+            self.modified.lock().unwrap().insert(path.to_path_buf(), UNIX_EPOCH + Duration::from_secs(seconds));
+        }
+    }
+
+    impl FileBackend for Dated {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            self.files.read(path)
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.files.write(path, contents)
+        }
+
+        fn append(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.files.append(path, contents)
+        }
+
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            self.files.remove(path)
+        }
+
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            self.files.canonicalize(path)
+        }
+
+        fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+            self.modified.lock().unwrap().get(path).copied().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file in memory"))
+        }
+    }
+
+    fn zone(domain: &str) -> String {
+        format!("zone \"{}\" {{type master; file \"/db\";}};\n", domain)
+    }
+
+    fn sorted(domains: HashSet<String>) -> Vec<String> {
+        let mut domains: Vec<String> = domains.into_iter().collect();
+        domains.sort();
+        domains
+    }
+
+    #[test]
+    fn a_changed_include_invalidates_the_cache() {
+        let backend = Arc::new(Dated::default());
+        let config = Config { zone_cache: Some(PathBuf::from("/var/cache/zones.bin")), backend: backend.clone(), ..Config::default() };
+        let include = Path::new("/etc/bind/included.zones");
+        backend.set(&config.zones_file, &format!("{}include \"{}\";\n", zone("a.example"), include.display()), 100);
+        backend.set(include, &zone("b.example"), 100);
+
+        assert_eq!(sorted(zone_set(&config).unwrap()), vec!["a.example", "b.example"]);
+        assert!(backend.exists(config.zone_cache.as_ref().unwrap()));
+
+        // Still the cached set while the mtimes are the same, even though the contents aren't
+        backend.files.write(include, zone("c.example").as_bytes()).unwrap();
+        assert_eq!(sorted(zone_set(&config).unwrap()), vec!["a.example", "b.example"]);
+
+        backend.set(include, &zone("c.example"), 200);
+        assert_eq!(sorted(zone_set(&config).unwrap()), vec!["a.example", "c.example"]);
+    }
+}
@@ -0,0 +1,280 @@
+/*
+* Bind Manager; a CLI tool to manage BIND blacklisted zones.
+* Copyright (c) 2024 TheFinnaCompany Ltd
+*/
+
+use std::path::{Path, PathBuf};
+
+use crate::backend::FileBackend;
+use crate::error::{Error, Result};
+
+// A zones file and its contents, as read while following includes
+pub struct ZoneSource {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+// No zone statement is anywhere near this long, longer lines are junk from a bad paste or a damaged disk.
+// This only decides what is reported and skipped as junk, files are still read whole.
+pub const MAX_LINE_LEN: usize = 4096;
+
+// The addresses a hosts-style block line may point a domain at
+const SINKHOLE_ADDRESSES: &[&str] = &["0.0.0.0", "127.0.0.1", "::", "::1"];
+
+pub fn parse_domain_from_line(line: &str) -> Option<String> {
+    if is_comment_or_blank(line) || junk_line(line).is_some() {
+        return None;
+    }
+    // A simple parser for the domain in the line. Adjust regex as needed.
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    // A hosts-style `0.0.0.0 domain` line
+    if let [address, name, ..] = parts[..] {
+        if SINKHOLE_ADDRESSES.contains(&address) && !name.starts_with('#') {
+            return Some(name.to_string());
+        }
+    }
+    if let Some(part) = parts.get(1) {
+        if part.starts_with('"') && part.ends_with('"') {
+            return Some(part.trim_matches('"').to_string());
+        }
+    }
+    None
+}
+
+pub struct ZoneStatement {
+    pub name: String,
+    pub zone_type: Option<String>,
+    pub file: Option<String>,
+}
+
+pub fn parse_zone_statement(line: &str) -> Option<ZoneStatement> {
+    // Expects the single-line form written by add_domain: zone "name" {type ...; file "...";};
+    let rest = line.trim().strip_prefix("zone")?.trim_start().strip_prefix('"')?;
+    let (name, rest) = rest.split_once('"')?;
+    // Skip an optional class (zone "name" IN { ... })
+    let body = &rest[rest.find('{')? + 1..];
+    let body = &body[..body.rfind('}')?];
+
+    let mut zone_type = None;
+    let mut file = None;
+    for clause in body.split(';').map(str::trim) {
+        if let Some(value) = clause.strip_prefix("type ") {
+            zone_type = Some(value.trim().to_string());
+        } else if let Some(value) = clause.strip_prefix("file ") {
+            file = Some(value.trim().trim_matches('"').to_string());
+        }
+    }
+
+    Some(ZoneStatement { name: name.to_string(), zone_type, file })
+}
+
+// What is wrong with a line that can't be part of a zones file at all: too long, or binary data
+// (which reading the file lossily turned into replacement characters)
+pub fn junk_line(line: &str) -> Option<&'static str> {
+    if line.len() > MAX_LINE_LEN {
+        return Some("line is too long to be a zone statement");
+    }
+    if line.chars().any(|c| c == char::REPLACEMENT_CHARACTER || (c.is_control() && c != '\t' && c != '\r')) {
+        return Some("line contains binary data");
+    }
+    None
+}
+
+// The junk lines of a file, by line number, so they can be reported rather than silently skipped
+pub fn junk_lines(contents: &str) -> Vec<(usize, &'static str)> {
+    contents.lines().enumerate().filter_map(|(idx, line)| junk_line(line).map(|problem| (idx + 1, problem))).collect()
+}
+
+pub fn is_comment_or_blank(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || ["//", "#", "/*", "*"].iter().any(|prefix| line.starts_with(prefix))
+}
+
+// Zone names only found commented out: in `//` or `#` line comments, or inside `/* */` blocks
+pub fn commented_domains(contents: &str) -> Vec<String> {
+    let mut domains = Vec::new();
+    let mut in_block = false;
+    for line in contents.lines() {
+        let mut line = line.trim();
+        let commented = in_block || is_comment_or_blank(line);
+        if line.starts_with("/*") {
+            in_block = true;
+        }
+        if let Some(end) = line.find("*/") {
+            in_block = false;
+            line = &line[..end];
+        }
+        if !commented {
+            continue;
+        }
+        let uncommented = line.trim_start_matches(['/', '#', '*', ' ', '\t']);
+        domains.extend(parse_domain_from_line(uncommented));
+    }
+    domains
+}
+
+// Recognises `include "path";` (named.conf) and `$INCLUDE path` (zone file) directives
+pub fn parse_include(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix("include") {
+        let rest = rest.trim_start().strip_prefix('"')?;
+        let (path, _) = rest.split_once('"')?;
+        return Some(path);
+    }
+    line.strip_prefix("$INCLUDE")?.split_whitespace().next()
+}
+
+// Reads the zones file followed by every file it includes, in the order they are included.
+// Relative include paths are resolved against the directory of the file that includes them.
+//
+// Each file is read whole through a single open, and every writer replaces files with a rename,
+// so a reader always gets a complete version of each file even while an add or del is running. The
+// one exception is a batched import, which appends each batch and can be read half way through one.
+pub fn read_sources(backend: &dyn FileBackend, path: &Path) -> Result<Vec<ZoneSource>> {
+    let mut sources = Vec::new();
+    read_source_into(backend, path, &mut sources, &mut Vec::new())?;
+    Ok(sources)
+}
+
+// `chain` is the stack of files including this one, to refuse include loops instead of recursing forever
+fn read_source_into(backend: &dyn FileBackend, path: &Path, sources: &mut Vec<ZoneSource>, chain: &mut Vec<PathBuf>) -> Result<()> {
+    let canonical = backend.canonicalize(path).map_err(Error::reading(path))?;
+    if let Some(start) = chain.iter().position(|included| *included == canonical) {
+        let cycle: Vec<String> = chain[start..].iter().chain([&canonical]).map(|path| path.display().to_string()).collect();
+        return Err(Error::Config(format!("Include loop, the zones files include each other: {}", cycle.join(" -> "))));
+    }
+
+    // Read lossily, so a damaged file still lists; the damaged lines are then skipped as junk
+    let contents = String::from_utf8_lossy(&backend.read(path).map_err(Error::reading(path))?).into_owned();
+    let includes: Vec<PathBuf> = contents
+        .lines()
+        .filter(|line| junk_line(line).is_none())
+        .filter_map(parse_include)
+        .map(|include| match path.parent() {
+            Some(parent) => parent.join(include),
+            None => PathBuf::from(include),
+        })
+        .collect();
+
+    sources.push(ZoneSource { path: path.to_path_buf(), contents });
+    chain.push(canonical);
+    for include in includes {
+        read_source_into(backend, &include, sources, chain)?;
+    }
+    chain.pop();
+    Ok(())
+}
+
+// Files smaller than this are parsed on one thread, spreading them out costs more than it saves
+#[cfg(feature = "parallel")]
+const PARALLEL_THRESHOLD: usize = 4 * 1024 * 1024;
+
+// Every zone name across the zones file and its includes
+pub fn read_domains(backend: &dyn FileBackend, path: &Path) -> Result<Vec<String>> {
+    Ok(domains_in(&read_sources(backend, path)?))
+}
+
+pub fn domains_in(sources: &[ZoneSource]) -> Vec<String> {
+    sources.iter().flat_map(|source| parse_domains(&source.contents)).collect()
+}
+
+// The zone names in one file, in file order
+#[cfg(feature = "parallel")]
+fn parse_domains(contents: &str) -> Vec<String> {
+    use rayon::prelude::*;
+
+    if contents.len() < PARALLEL_THRESHOLD {
+        return contents.lines().filter_map(parse_domain_from_line).collect();
+    }
+    contents.par_lines().filter_map(parse_domain_from_line).collect()
+}
+
+#[cfg(not(feature = "parallel"))]
+fn parse_domains(contents: &str) -> Vec<String> {
+    contents.lines().filter_map(parse_domain_from_line).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use crate::atomic;
+    use crate::backend::{Filesystem, InMemory};
+
+    fn zones(count: usize, prefix: &str) -> String {
+        (0..count).map(|idx| format!("zone \"{}{}.example\" {{type master; file \"/db\";}};\n\n", prefix, idx)).collect()
+    }
+
+    #[test]
+    fn a_file_including_itself_is_refused() {
+        let memory = InMemory::new()
+            .with_file("/etc/bind/blacklisted.zones", "zone \"a.example\" {type master; file \"/db\";};\ninclude \"blacklisted.zones\";\n");
+        match read_sources(&memory, Path::new("/etc/bind/blacklisted.zones")) {
+            Err(Error::Config(message)) => assert!(message.ends_with("/etc/bind/blacklisted.zones -> /etc/bind/blacklisted.zones"), "{}", message),
+            other => panic!("expected an include loop error, got {:?}", other.map(|sources| sources.len())),
+        }
+    }
+
+    #[test]
+    fn an_include_cycle_is_refused_with_the_loop() {
+        let memory = InMemory::new()
+            .with_file("/etc/bind/blacklisted.zones", "include \"extra.zones\";\n")
+            .with_file("/etc/bind/extra.zones", "$INCLUDE /etc/bind/more.zones\n")
+            .with_file("/etc/bind/more.zones", "include \"/etc/bind/extra.zones\";\n");
+        match read_sources(&memory, Path::new("/etc/bind/blacklisted.zones")) {
+            Err(Error::Config(message)) => {
+                assert!(message.ends_with("/etc/bind/extra.zones -> /etc/bind/more.zones -> /etc/bind/extra.zones"), "{}", message)
+            }
+            other => panic!("expected an include loop error, got {:?}", other.map(|sources| sources.len())),
+        }
+    }
+
+    #[test]
+    fn a_read_during_writes_sees_a_whole_version() {
+        let dir = std::env::temp_dir().join(format!("bind_manager-zones-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("blacklisted.zones");
+        // Large enough that a write in place would be caught halfway
+        let versions = [zones(20_000, "old"), zones(30_000, "new")];
+        fs::write(&path, &versions[0]).unwrap();
+
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for round in 0..20 {
+                    atomic::write(&path, versions[(round + 1) % 2].as_bytes()).unwrap();
+                }
+                done.store(true, Ordering::SeqCst);
+            });
+            let mut reads = 0;
+            while !done.load(Ordering::SeqCst) || reads < 20 {
+                let sources = read_sources(&Filesystem, &path).unwrap();
+                assert!(versions.contains(&sources[0].contents), "read a partial zones file of {} bytes", sources[0].contents.len());
+                reads += 1;
+            }
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_read_in_the_middle_of_a_write_sees_the_old_version() {
+        let dir = std::env::temp_dir().join(format!("bind_manager-zones-mid-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("blacklisted.zones");
+        let (old, new) = (zones(5_000, "old"), zones(8_000, "new"));
+        fs::write(&path, &old).unwrap();
+
+        // Runs once the new contents are on disk in the temp file, just before they replace the old
+        let read_mid_write = |written: &[u8]| {
+            assert_eq!(written, new.as_bytes());
+            let sources = read_sources(&Filesystem, &path).map_err(|err| err.to_string())?;
+            assert_eq!(sources[0].contents, old);
+            Ok(())
+        };
+        atomic::write_verified(&path, new.as_bytes(), &read_mid_write).unwrap();
+        assert_eq!(read_sources(&Filesystem, &path).unwrap()[0].contents, new);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}